@@ -0,0 +1,181 @@
+use crate::vm::{Instruction, Line};
+
+/// How strictly programs that may not reach END are treated when compiling
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum EndCheck {
+    Off,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum CompileWarning {
+    /// Execution can run past the last instruction after this line
+    FallsOffEnd {
+        file: String,
+        line: u32,
+    },
+    /// END can't be reached once execution gets here, the program loops forever or fails
+    EndUnreachable {
+        file: String,
+        line: u32,
+    },
+}
+
+/// Index of the first instruction at or after `index`
+fn instruction_at(lines: &[Line], index: usize) -> Option<usize> {
+    (index..lines.len()).find(|i| lines[*i].instruction.is_some())
+}
+
+/// Successors of an instruction in the control flow graph, `None` stands for running
+/// past the last instruction. A RET may return behind any CALL.
+fn successors(lines: &[Line], index: usize, return_sites: &[Option<usize>]) -> Vec<Option<usize>> {
+    let next = instruction_at(lines, index + 1);
+    match &lines[index].instruction {
+        Some(Instruction::Goto(jump)) | Some(Instruction::Call(jump)) => vec![instruction_at(lines, jump.target as usize)],
+        Some(Instruction::JumpIfZero(jump)) | Some(Instruction::JumpIfNotZero(jump)) => {
+            vec![instruction_at(lines, jump.target as usize), next]
+        },
+        Some(Instruction::Return()) => return_sites.to_vec(),
+        Some(Instruction::End()) | None => Vec::new(),
+        Some(_) => vec![next],
+    }
+}
+
+/// Checks that every path from the first instruction reaches END. `file` is reported
+/// for programs without any instruction.
+pub fn check_end(lines: &[Line], file: &str) -> Vec<CompileWarning> {
+    let entry = match instruction_at(lines, 0) {
+        Some(entry) => entry,
+        None => return vec![CompileWarning::FallsOffEnd { file: file.to_owned(), line: 0 }],
+    };
+
+    let return_sites: Vec<Option<usize>> = lines.iter().enumerate()
+        .filter(|(_, line)| matches!(line.instruction, Some(Instruction::Call(_))))
+        .map(|(index, _)| instruction_at(lines, index + 1))
+        .collect();
+    let graph: Vec<Vec<Option<usize>>> = (0..lines.len())
+        .map(|index| successors(lines, index, &return_sites))
+        .collect();
+
+    // Instructions reachable from the entry
+    let mut reachable = vec![false; lines.len()];
+    let mut stack = vec![entry];
+    reachable[entry] = true;
+    while let Some(index) = stack.pop() {
+        for next in graph[index].iter().flatten() {
+            if !reachable[*next] {
+                reachable[*next] = true;
+                stack.push(*next);
+            }
+        }
+    }
+
+    // Instructions that can leave the program, by END or by running past the last instruction
+    let mut exits: Vec<bool> = (0..lines.len())
+        .map(|index| matches!(lines[index].instruction, Some(Instruction::End())) || graph[index].contains(&None))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..lines.len() {
+            if !exits[index] && graph[index].iter().flatten().any(|next| exits[*next]) {
+                exits[index] = true;
+                changed = true;
+            }
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for index in (0..lines.len()).filter(|index| reachable[*index]) {
+        let line = &lines[index];
+        if graph[index].contains(&None) {
+            warnings.push(CompileWarning::FallsOffEnd { file: line.file_name.clone(), line: line.line_number });
+        } else if !exits[index] {
+            // Only report where execution enters a region it can never leave
+            let entered = index == entry || (0..lines.len()).any(|from| {
+                reachable[from] && exits[from] && graph[from].contains(&Some(index))
+            });
+            if entered {
+                warnings.push(CompileWarning::EndUnreachable { file: line.file_name.clone(), line: line.line_number });
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use super::*;
+    use crate::vm::{CompileError, VirtualMachine};
+
+    fn compile(source: &str, end_check: EndCheck) -> Result<VirtualMachine, CompileError> {
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        vm.end_check = end_check;
+        let sources = BTreeMap::from([("test.rm".to_owned(), source.to_owned())]);
+        vm.load_sources(&PathBuf::from("test.rm"), &sources)?;
+        Ok(vm)
+    }
+
+    fn warnings(source: &str) -> Vec<CompileWarning> {
+        let vm = compile(source, EndCheck::Off).unwrap();
+        check_end(&vm.lines, "test.rm")
+    }
+
+    fn falls_off(line: u32) -> CompileWarning {
+        CompileWarning::FallsOffEnd { file: "test.rm".to_owned(), line }
+    }
+
+    fn unreachable(line: u32) -> CompileWarning {
+        CompileWarning::EndUnreachable { file: "test.rm".to_owned(), line }
+    }
+
+    #[test]
+    fn straight_line_reaches_end() {
+        assert_eq!(warnings("LOAD #1\nADD #2\nEND\n"), vec![]);
+    }
+
+    #[test]
+    fn empty_program_falls_off() {
+        assert_eq!(warnings(""), vec![falls_off(0)]);
+    }
+
+    #[test]
+    fn missing_end_falls_off() {
+        assert_eq!(warnings("LOAD #1\nADD #2\n"), vec![falls_off(1)]);
+        assert_eq!(warnings("LOAD #1\nJZERO done\nEND\ndone: ADD #1\n"), vec![falls_off(3)]);
+    }
+
+    #[test]
+    fn endless_loop_is_reported_once() {
+        // Nothing before the loop can reach END either, so the region starts at the entry
+        assert_eq!(warnings("LOAD #1\nloop: GOTO loop\nEND\n"), vec![unreachable(0)]);
+    }
+
+    #[test]
+    fn loop_entered_from_a_branch() {
+        let source = "LOAD #1\nJZERO done\nloop: ADD #1\nGOTO loop\ndone: END\n";
+        assert_eq!(warnings(source), vec![unreachable(2)]);
+    }
+
+    #[test]
+    fn counting_loop_reaches_end() {
+        assert_eq!(warnings("LOAD #3\nloop: SUB #1\nJNZERO loop\nEND\n"), vec![]);
+        assert_eq!(warnings("LOAD #3\nloop: JZERO done\nSUB #1\nGOTO loop\ndone: END\n"), vec![]);
+    }
+
+    #[test]
+    fn call_returns_behind_the_call() {
+        assert_eq!(warnings("CALL sub\nEND\nsub: LOAD #1\nRET\n"), vec![]);
+        assert_eq!(warnings("CALL sub\nEND\nsub: GOTO sub\n"), vec![unreachable(0)]);
+    }
+
+    #[test]
+    fn error_profile_rejects_compilation() {
+        let error = compile("loop: GOTO loop\nEND\n", EndCheck::Error).unwrap_err();
+        assert_eq!(error, CompileError::EndNotReached { file: "test.rm".to_owned(), line: 0 });
+        assert_eq!(compile("loop: GOTO loop\nEND\n", EndCheck::Warning).unwrap().warnings, vec![unreachable(0)]);
+    }
+}
@@ -0,0 +1,291 @@
+use num_bigint::BigInt;
+use crate::memory::Registers;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A register read or written while executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct RegisterAccess {
+    pub register: usize,
+    pub kind: AccessKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Accumulator,
+    Register(usize),
+    Value(BigInt),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+/// Condition like `acc == 0` or `c(3) > 10`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Condition {
+    pub text: String,
+    #[serde(skip)]
+    pub left: Operand,
+    #[serde(skip)]
+    pub comparison: Comparison,
+    #[serde(skip)]
+    pub right: Operand,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum BreakpointKind {
+    /// Stops before the instruction on a line is executed, `line` is zero based
+    Line {
+        file: String,
+        line: u32,
+    },
+    /// Stops after an instruction accessed a register
+    Watch {
+        register: usize,
+        /// `None` watches both reads and writes
+        access: Option<AccessKind>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Breakpoint {
+    pub id: u32,
+    pub kind: BreakpointKind,
+    pub condition: Option<Condition>,
+    /// Only stop once the breakpoint was reached this many times
+    pub hit_count: Option<u32>,
+    /// Logpoints record this message instead of stopping, `{acc}` and `{c(n)}` are replaced
+    pub log_message: Option<String>,
+    pub hits: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct BreakpointHit {
+    pub id: u32,
+    pub kind: BreakpointKind,
+    pub hits: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Breakpoints {
+    pub breakpoints: Vec<Breakpoint>,
+    /// Messages recorded by logpoints that were not yet fetched
+    pub messages: Vec<String>,
+    next_id: u32,
+}
+
+fn parse_operand(text: &str) -> Option<Operand> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("acc") {
+        return Some(Operand::Accumulator);
+    }
+    if let Some(register) = text.strip_prefix("c(").and_then(|rest| rest.strip_suffix(")")) {
+        return register.trim().parse::<usize>().ok().filter(|r| *r > 0).map(Operand::Register);
+    }
+    text.parse::<BigInt>().ok().map(Operand::Value)
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Condition> {
+        // Two character operators first, so `<=` isn't read as `<`
+        let operators = [
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessEqual),
+            (">=", Comparison::GreaterEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ];
+
+        for (operator, comparison) in operators {
+            if let Some((left, right)) = text.split_once(operator) {
+                return Some(Condition {
+                    text: text.trim().to_owned(),
+                    left: parse_operand(left)?,
+                    comparison,
+                    right: parse_operand(right)?,
+                });
+            }
+        }
+
+        None
+    }
+
+    pub fn evaluate<M: Registers>(&self, accumulator: &BigInt, memory: &M) -> bool {
+        let left = evaluate_operand(&self.left, accumulator, memory);
+        let right = evaluate_operand(&self.right, accumulator, memory);
+        match self.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+        }
+    }
+}
+
+fn evaluate_operand<M: Registers>(operand: &Operand, accumulator: &BigInt, memory: &M) -> BigInt {
+    match operand {
+        Operand::Accumulator => accumulator.clone(),
+        Operand::Register(register) => memory.peek(*register),
+        Operand::Value(value) => value.clone(),
+    }
+}
+
+/// Replaces `{acc}` and `{c(n)}` in a logpoint message
+fn format_message<M: Registers>(message: &str, accumulator: &BigInt, memory: &M) -> String {
+    let mut result = String::new();
+    let mut rest = message;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let expression = &rest[start + 1..start + end];
+                match parse_operand(expression) {
+                    Some(operand) => result.push_str(&evaluate_operand(&operand, accumulator, memory).to_string()),
+                    None => result.push_str(&rest[start..start + end + 1]),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Compares file names independent of the path separator
+pub fn same_file(a: &str, b: &str) -> bool {
+    a.replace('\\', "/") == b.replace('\\', "/")
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, kind: BreakpointKind, condition: Option<Condition>, hit_count: Option<u32>, log_message: Option<String>) -> u32 {
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id: self.next_id,
+            kind,
+            condition,
+            hit_count,
+            log_message,
+            hits: 0,
+        });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        len != self.breakpoints.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Forgets hit counts and messages, e.g. when a program is recompiled
+    pub fn reset_hits(&mut self) {
+        for breakpoint in self.breakpoints.iter_mut() {
+            breakpoint.hits = 0;
+        }
+        self.messages.clear();
+    }
+
+    /// Evaluates all breakpoints matching `applies`. Logpoints record their message,
+    /// the first other breakpoint whose condition and hit count are met is returned.
+    fn trigger<M, F>(&mut self, accumulator: &BigInt, memory: &M, applies: F) -> Option<BreakpointHit>
+    where
+        M: Registers,
+        F: Fn(&BreakpointKind) -> bool,
+    {
+        let mut hit = None;
+        for breakpoint in self.breakpoints.iter_mut() {
+            if !applies(&breakpoint.kind) {
+                continue;
+            }
+            if let Some(condition) = &breakpoint.condition {
+                if !condition.evaluate(accumulator, memory) {
+                    continue;
+                }
+            }
+
+            breakpoint.hits += 1;
+            if breakpoint.hits < breakpoint.hit_count.unwrap_or(0) {
+                continue;
+            }
+
+            if let Some(message) = &breakpoint.log_message {
+                self.messages.push(format_message(message, accumulator, memory));
+            } else if hit.is_none() {
+                hit = Some(BreakpointHit {
+                    id: breakpoint.id,
+                    kind: breakpoint.kind.clone(),
+                    hits: breakpoint.hits,
+                });
+            }
+        }
+        hit
+    }
+
+    /// Like `trigger`, but without counting hits or recording log messages, for stepping backwards
+    fn find<M, F>(&self, accumulator: &BigInt, memory: &M, applies: F) -> Option<BreakpointHit>
+    where
+        M: Registers,
+        F: Fn(&BreakpointKind) -> bool,
+    {
+        self.breakpoints.iter()
+            .filter(|b| b.log_message.is_none() && applies(&b.kind))
+            .find(|b| match &b.condition {
+                Some(condition) => condition.evaluate(accumulator, memory),
+                None => true,
+            })
+            .map(|b| BreakpointHit { id: b.id, kind: b.kind.clone(), hits: b.hits })
+    }
+
+    pub fn check_line<M: Registers>(&mut self, file: &str, line: u32, accumulator: &BigInt, memory: &M) -> Option<BreakpointHit> {
+        self.trigger(accumulator, memory, |kind| line_matches(kind, file, line))
+    }
+
+    pub fn check_accesses<M: Registers>(&mut self, accesses: &[RegisterAccess], accumulator: &BigInt, memory: &M) -> Option<BreakpointHit> {
+        self.trigger(accumulator, memory, |kind| accesses_match(kind, accesses))
+    }
+
+    pub fn find_line<M: Registers>(&self, file: &str, line: u32, accumulator: &BigInt, memory: &M) -> Option<BreakpointHit> {
+        self.find(accumulator, memory, |kind| line_matches(kind, file, line))
+    }
+
+    pub fn find_accesses<M: Registers>(&self, accesses: &[RegisterAccess], accumulator: &BigInt, memory: &M) -> Option<BreakpointHit> {
+        self.find(accumulator, memory, |kind| accesses_match(kind, accesses))
+    }
+}
+
+fn line_matches(kind: &BreakpointKind, file: &str, line: u32) -> bool {
+    match kind {
+        BreakpointKind::Line { file: f, line: l } => *l == line && same_file(f, file),
+        _ => false,
+    }
+}
+
+fn accesses_match(kind: &BreakpointKind, accesses: &[RegisterAccess]) -> bool {
+    match kind {
+        BreakpointKind::Watch { register, access } => accesses.iter().any(|a| {
+            a.register == *register && access.unwrap_or(a.kind) == a.kind
+        }),
+        _ => false,
+    }
+}
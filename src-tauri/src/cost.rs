@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use crate::memory::Registers;
+use crate::tapes::Tapes;
+use crate::vm::{Instruction, PtrType, RefPtrType, OPCODES};
+
+/// Accumulated cost of the executed steps under the different cost measures
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Cost {
+    /// Every instruction costs 1
+    pub uniform: u64,
+    /// Every instruction costs the bit lengths of the addresses and values it involves
+    pub logarithmic: u64,
+    /// Every instruction costs the weight of its opcode
+    pub weighted: u64,
+}
+
+impl Cost {
+    pub fn add(&mut self, other: Cost) {
+        self.uniform = self.uniform.saturating_add(other.uniform);
+        self.logarithmic = self.logarithmic.saturating_add(other.logarithmic);
+        self.weighted = self.weighted.saturating_add(other.weighted);
+    }
+
+    /// The cost accrued since `earlier`
+    pub fn since(&self, earlier: Cost) -> Cost {
+        Cost {
+            uniform: self.uniform.saturating_sub(earlier.uniform),
+            logarithmic: self.logarithmic.saturating_sub(earlier.logarithmic),
+            weighted: self.weighted.saturating_sub(earlier.weighted),
+        }
+    }
+}
+
+/// Per-opcode weights for the weighted cost, opcodes without a weight cost 1
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CostModel {
+    weights: HashMap<String, u64>,
+}
+
+/// Length of a number in the logarithmic cost measure, L(0) = 1
+pub fn length(value: &BigInt) -> u64 {
+    value.bits().max(1)
+}
+
+impl CostModel {
+    pub fn weights(&self) -> &HashMap<String, u64> {
+        &self.weights
+    }
+
+    /// Replaces the weight table, opcodes are case insensitive
+    pub fn set_weights(&mut self, weights: HashMap<String, u64>) -> Result<(), String> {
+        let mut table = HashMap::new();
+        for (opcode, weight) in weights {
+            let opcode = opcode.to_ascii_uppercase();
+            if !OPCODES.contains(&opcode.as_str()) {
+                return Err(format!("Unknown opcode {}", opcode));
+            }
+            table.insert(opcode, weight);
+        }
+        self.weights = table;
+        Ok(())
+    }
+
+    pub fn weight(&self, opcode: &str) -> u64 {
+        self.weights.get(opcode).copied().unwrap_or(1)
+    }
+
+    /// Cost of executing `instruction` in the given state
+    pub fn cost<M: Registers>(&self, instruction: &Instruction, accumulator: &BigInt, memory: &M, tapes: &Tapes) -> Cost {
+        Cost {
+            uniform: 1,
+            logarithmic: logarithmic_cost(instruction, accumulator, memory, tapes),
+            weighted: self.weight(instruction.opcode()),
+        }
+    }
+}
+
+fn peek<M: Registers>(memory: &M, index: &BigInt) -> BigInt {
+    index.to_usize().map(|index| memory.peek(index)).unwrap_or_default()
+}
+
+/// Cost of reading an operand: the address itself plus every value read on the way
+fn operand_cost<M: Registers>(ptr: &PtrType, memory: &M) -> u64 {
+    match ptr {
+        PtrType::Immediate(value) => length(value),
+        PtrType::Register(i) => {
+            let index = BigInt::from(i.get());
+            length(&index) + length(&peek(memory, &index))
+        },
+        PtrType::Pointer(i) => {
+            let index = BigInt::from(i.get());
+            let pointer = peek(memory, &index);
+            length(&index) + length(&pointer) + length(&peek(memory, &pointer))
+        },
+    }
+}
+
+/// Cost of writing a register, the address plus the pointer followed on the way
+fn target_cost<M: Registers>(ptr: &RefPtrType, memory: &M) -> u64 {
+    match ptr {
+        RefPtrType::Register(i) => length(&BigInt::from(i.get())),
+        RefPtrType::Pointer(i) => {
+            let index = BigInt::from(i.get());
+            length(&index) + length(&peek(memory, &index))
+        },
+    }
+}
+
+fn logarithmic_cost<M: Registers>(instruction: &Instruction, accumulator: &BigInt, memory: &M, tapes: &Tapes) -> u64 {
+    match instruction {
+        Instruction::Load(ptr) => operand_cost(ptr, memory),
+        Instruction::Add(ptr) | Instruction::Sub(ptr) | Instruction::Mul(ptr) | Instruction::Div(ptr) => {
+            length(accumulator) + operand_cost(ptr, memory)
+        },
+        Instruction::Store(ptr) => length(accumulator) + target_cost(ptr, memory),
+        Instruction::Read(ptr) => {
+            // An exhausted input fails the step, so its cost doesn't matter
+            let value = tapes.peek().map_or(1, length);
+            value + ptr.as_ref().map_or(0, |ptr| target_cost(ptr, memory))
+        },
+        Instruction::Write(Some(ptr)) => operand_cost(ptr, memory),
+        Instruction::Write(None) => length(accumulator),
+        Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_) => length(accumulator),
+        Instruction::Goto(_) | Instruction::Call(_) | Instruction::Return() | Instruction::End() => 1,
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashSet};
+use crate::breakpoints::same_file;
+use crate::vm::{Instruction, Line};
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CoverageFormat {
+    Json,
+    Lcov,
+}
+
+/// Counters of a single instruction line
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct LineCounters {
+    hits: u64,
+    /// Taken and not taken counts, only for conditional jumps
+    branches: Option<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct LineCoverage {
+    /// One based line number
+    pub line: u32,
+    pub hits: u64,
+    pub taken: Option<u64>,
+    pub not_taken: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FileCoverage {
+    pub file: String,
+    pub lines_found: usize,
+    pub lines_hit: usize,
+    /// Every conditional jump has two branches, taken and not taken
+    pub branches_found: usize,
+    pub branches_hit: usize,
+    pub lines: Vec<LineCoverage>,
+}
+
+/// Instructions and branch directions exercised across runs. Unlike the profiler this
+/// survives recompiling, counters are kept per source line of each file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Coverage {
+    files: BTreeMap<String, BTreeMap<u32, LineCounters>>,
+}
+
+impl Coverage {
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// Adds the instructions of a freshly compiled program, so unexecuted lines are reported.
+    /// Lines of its files that no longer hold an instruction are forgotten.
+    pub fn register(&mut self, lines: &[Line]) {
+        let files: HashSet<&str> = lines.iter().map(|line| line.file_name.as_str()).collect();
+        for file in files {
+            let instructions: Vec<&Line> = lines.iter()
+                .filter(|line| line.instruction.is_some() && same_file(&line.file_name, file))
+                .collect();
+            let counters = self.files.entry(file.to_owned()).or_default();
+            counters.retain(|number, _| instructions.iter().any(|line| line.line_number == *number));
+            for line in instructions {
+                let counter = counters.entry(line.line_number).or_default();
+                if let Some(Instruction::JumpIfZero(_) | Instruction::JumpIfNotZero(_)) = line.instruction {
+                    counter.branches.get_or_insert((0, 0));
+                }
+            }
+        }
+    }
+
+    /// Counts an execution, `branch` tells whether a conditional jump was taken
+    pub fn record(&mut self, file: &str, line: u32, branch: Option<bool>) {
+        let counters = self.files.entry(file.to_owned()).or_default().entry(line).or_default();
+        counters.hits += 1;
+        if let Some(taken) = branch {
+            let (taken_count, not_taken_count) = counters.branches.get_or_insert((0, 0));
+            if taken {
+                *taken_count += 1;
+            } else {
+                *not_taken_count += 1;
+            }
+        }
+    }
+
+    /// Takes back an execution recorded by `record`, for stepping backwards
+    pub fn unrecord(&mut self, file: &str, line: u32, branch: Option<bool>) {
+        if let Some(counters) = self.files.get_mut(file).and_then(|lines| lines.get_mut(&line)) {
+            counters.hits = counters.hits.saturating_sub(1);
+            if let (Some((taken_count, not_taken_count)), Some(taken)) = (&mut counters.branches, branch) {
+                if taken {
+                    *taken_count = taken_count.saturating_sub(1);
+                } else {
+                    *not_taken_count = not_taken_count.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    pub fn report(&self) -> Vec<FileCoverage> {
+        self.files.iter().map(|(file, counters)| {
+            let lines: Vec<LineCoverage> = counters.iter().map(|(number, counter)| LineCoverage {
+                line: number + 1,
+                hits: counter.hits,
+                taken: counter.branches.map(|(taken, _)| taken),
+                not_taken: counter.branches.map(|(_, not_taken)| not_taken),
+            }).collect();
+            let branches: Vec<u64> = lines.iter()
+                .flat_map(|line| line.taken.into_iter().chain(line.not_taken))
+                .collect();
+
+            FileCoverage {
+                file: file.clone(),
+                lines_found: lines.len(),
+                lines_hit: lines.iter().filter(|line| line.hits > 0).count(),
+                branches_found: branches.len(),
+                branches_hit: branches.iter().filter(|count| **count > 0).count(),
+                lines,
+            }
+        }).collect()
+    }
+
+    pub fn export(&self, format: CoverageFormat) -> Result<String, String> {
+        match format {
+            CoverageFormat::Json => serde_json::to_string_pretty(&self.report()).map_err(|e| e.to_string()),
+            CoverageFormat::Lcov => Ok(self.to_lcov()),
+        }
+    }
+
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for file in self.report() {
+            lcov.push_str("TN:\n");
+            lcov.push_str(&format!("SF:{}\n", file.file));
+            for line in &file.lines {
+                if let (Some(taken), Some(not_taken)) = (line.taken, line.not_taken) {
+                    // Branches of lines that never ran are reported as "-"
+                    let count = |count: u64| if line.hits > 0 { count.to_string() } else { "-".to_owned() };
+                    lcov.push_str(&format!("BRDA:{},0,0,{}\n", line.line, count(taken)));
+                    lcov.push_str(&format!("BRDA:{},0,1,{}\n", line.line, count(not_taken)));
+                }
+            }
+            lcov.push_str(&format!("BRF:{}\nBRH:{}\n", file.branches_found, file.branches_hit));
+            for line in &file.lines {
+                lcov.push_str(&format!("DA:{},{}\n", line.line, line.hits));
+            }
+            lcov.push_str(&format!("LF:{}\nLH:{}\n", file.lines_found, file.lines_hit));
+            lcov.push_str("end_of_record\n");
+        }
+        lcov
+    }
+}
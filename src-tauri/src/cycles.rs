@@ -0,0 +1,103 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use num_bigint::BigInt;
+use num_traits::Zero;
+use crate::memory::Registers;
+
+/// States remembered before starting over, bounds the memory used on long runs
+const MAX_STATES: usize = 1 << 20;
+
+/// Detects non-termination by remembering a hash of every machine state. The machine
+/// is deterministic, so reaching a state a second time means it loops forever.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CycleDetector {
+    pub enabled: bool,
+    /// State hash to the step it was first seen at
+    seen: HashMap<u64, u64>,
+}
+
+impl CycleDetector {
+    /// Forgets all states, required whenever the machine is changed from outside
+    pub fn clear(&mut self) {
+        self.seen.clear();
+    }
+
+    /// The step `state` was first seen at, if it was seen before
+    pub fn check(&self, state: u64) -> Option<u64> {
+        self.seen.get(&state).copied()
+    }
+
+    /// Remembers the state at `step`. After `MAX_STATES` states it starts over, so a cycle
+    /// may be reported starting later than it actually did.
+    pub fn record(&mut self, state: u64, step: u64) {
+        if self.seen.len() >= MAX_STATES {
+            self.seen.clear();
+        }
+        self.seen.entry(state).or_insert(step);
+    }
+}
+
+/// Hash of everything that determines how the machine continues
+pub fn hash_state<M: Registers>(line_ptr: u32, accumulator: &BigInt, memory: &M, call_stack: &[u32], input_position: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line_ptr.hash(&mut hasher);
+    accumulator.hash(&mut hasher);
+    // Touched registers holding 0 are the same as untouched ones
+    for (register, value) in memory.touched() {
+        if !value.is_zero() {
+            register.hash(&mut hasher);
+            value.hash(&mut hasher);
+        }
+    }
+    call_stack.hash(&mut hasher);
+    // The output doesn't influence how the machine continues, the remaining input does
+    input_position.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use crate::vm::{ExecutionError, ExecutionResult, VirtualMachine};
+
+    fn compile(source: &str) -> VirtualMachine {
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        vm.cycles.enabled = true;
+        let sources = BTreeMap::from([("test.rm".to_owned(), source.to_owned())]);
+        vm.load_sources(&PathBuf::from("test.rm"), &sources).unwrap();
+        vm
+    }
+
+    #[test]
+    fn detects_endless_loop() {
+        let mut vm = compile("LOAD #1\nloop: ADD #0\nGOTO loop\nEND\n");
+        let error = vm.run(100).unwrap_err();
+        assert_eq!(error.error, ExecutionError::NonTermination { start: 1, length: 2 });
+    }
+
+    #[test]
+    fn counting_loop_ends() {
+        let mut vm = compile("LOAD #3\nloop: SUB #1\nJNZERO loop\nEND\n");
+        assert!(matches!(vm.run(100).unwrap().result, ExecutionResult::End { .. }));
+    }
+
+    #[test]
+    fn stepping_on_end_is_no_cycle() {
+        let mut vm = compile("LOAD #1\nEND\n");
+        vm.run(100).unwrap();
+        assert!(matches!(vm.step().unwrap(), ExecutionResult::End { .. }));
+        assert!(matches!(vm.step().unwrap(), ExecutionResult::End { .. }));
+    }
+
+    #[test]
+    fn failed_step_is_no_cycle() {
+        let mut vm = compile("LOAD #1\nDIV 2\nEND\n");
+        vm.step().unwrap();
+        assert_eq!(vm.step().unwrap_err().error, ExecutionError::DivThroughZero);
+        assert_eq!(vm.step().unwrap_err().error, ExecutionError::DivThroughZero);
+        vm.set_register(2, 1.into()).unwrap();
+        assert!(matches!(vm.run(100).unwrap().result, ExecutionResult::End { .. }));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::memory::Registers;
+use crate::vm::{Diagnostics, ExecutionResult, RuntimeError, Snapshot, VirtualMachine};
+
+/// What has to match between the two programs
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum DiffCriterion {
+    /// The accumulator after every step
+    Accumulator,
+    /// The given registers after every step
+    Registers(Vec<usize>),
+    /// The given result registers, only compared once both programs reached END
+    Outputs(Vec<usize>),
+    /// The values written to the output tape, as soon as they are written
+    OutputTape,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum Divergence {
+    Accumulator,
+    Register(usize),
+    /// Position of the first differing value on the output tape
+    OutputTape(usize),
+    /// Only one of the programs reached END
+    Ended,
+    /// At least one program failed, execution can't continue
+    Failed {
+        left: Option<RuntimeError>,
+        right: Option<RuntimeError>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum DiffResult {
+    /// Both programs reached END without diverging
+    Equal {
+        steps: u64,
+    },
+    Diverged {
+        /// Number of lockstep steps, including the diverging one
+        step: u64,
+        divergence: Divergence,
+        /// Lines executed by the diverging step
+        left_line: Option<Diagnostics>,
+        right_line: Option<Diagnostics>,
+        left: Box<Snapshot>,
+        right: Box<Snapshot>,
+    },
+    /// No divergence within the step budget
+    StepLimit {
+        steps: u64,
+    },
+}
+
+/// Executes a single step unless the program already ended
+fn advance<M: Registers>(vm: &mut VirtualMachine<M>, ended: &mut bool, line: &mut Option<Diagnostics>) -> Result<(), RuntimeError> {
+    if *ended {
+        return Ok(());
+    }
+    match vm.step()? {
+        ExecutionResult::End { line: executed, .. } => {
+            *ended = true;
+            *line = Some(executed);
+        }
+        ExecutionResult::Executed { line: executed, .. } => *line = Some(executed),
+    }
+    Ok(())
+}
+
+fn first_different_register<M: Registers>(left: &VirtualMachine<M>, right: &VirtualMachine<M>, registers: &[usize]) -> Option<usize> {
+    registers.iter().copied().find(|index| left.memory.peek(*index) != right.memory.peek(*index))
+}
+
+/// First position where the output tapes differ. While a program runs its tape may be
+/// shorter than the other one, once both ended the lengths have to match too.
+fn first_different_output<M: Registers>(left: &VirtualMachine<M>, right: &VirtualMachine<M>, ended: (bool, bool)) -> Option<usize> {
+    let (left, right) = (&left.tapes.output, &right.tapes.output);
+    let common = left.len().min(right.len());
+    match (0..common).find(|index| left[*index] != right[*index]) {
+        Some(index) => Some(index),
+        None if ended == (true, true) && left.len() != right.len() => Some(common),
+        None => None,
+    }
+}
+
+fn compare<M: Registers>(left: &VirtualMachine<M>, right: &VirtualMachine<M>, criterion: &DiffCriterion, ended: (bool, bool)) -> Option<Divergence> {
+    match criterion {
+        DiffCriterion::Outputs(registers) => match ended {
+            (true, true) => first_different_register(left, right, registers).map(Divergence::Register),
+            _ => None,
+        },
+        DiffCriterion::OutputTape => first_different_output(left, right, ended).map(Divergence::OutputTape),
+        _ if ended.0 != ended.1 => Some(Divergence::Ended),
+        DiffCriterion::Accumulator => (left.accumulator != right.accumulator).then_some(Divergence::Accumulator),
+        DiffCriterion::Registers(registers) => first_different_register(left, right, registers).map(Divergence::Register),
+    }
+}
+
+/// Puts both machines at the start of their programs, with the registers and input tape
+/// of `left`, so both runs start from the same state
+pub fn start_together<M: Registers>(left: &mut VirtualMachine<M>, right: &mut VirtualMachine<M>) {
+    let memory = left.memory.clone();
+    left.reset();
+    right.reset();
+    left.memory = memory.clone();
+    right.memory = memory;
+    right.tapes.set_input(left.tapes.input.clone());
+}
+
+/// Steps both machines in lockstep until their state differs by `criterion`. A program
+/// that reached END stays there while the other one continues.
+pub fn run_lockstep<M: Registers>(left: &mut VirtualMachine<M>, right: &mut VirtualMachine<M>, criterion: &DiffCriterion, max_steps: u64) -> DiffResult {
+    let (mut left_ended, mut right_ended) = (false, false);
+    let (mut left_line, mut right_line) = (None, None);
+    let mut steps = 0;
+
+    while steps < max_steps && !(left_ended && right_ended) {
+        steps += 1;
+        let left_result = advance(left, &mut left_ended, &mut left_line);
+        let right_result = advance(right, &mut right_ended, &mut right_line);
+
+        let divergence = match (left_result, right_result) {
+            (Ok(()), Ok(())) => compare(left, right, criterion, (left_ended, right_ended)),
+            (left_result, right_result) => Some(Divergence::Failed {
+                left: left_result.err(),
+                right: right_result.err(),
+            }),
+        };
+        if let Some(divergence) = divergence {
+            return DiffResult::Diverged {
+                step: steps,
+                divergence,
+                left_line,
+                right_line,
+                left: Box::new(left.snapshot()),
+                right: Box::new(right.snapshot()),
+            };
+        }
+    }
+
+    if left_ended && right_ended {
+        DiffResult::Equal { steps }
+    } else {
+        DiffResult::StepLimit { steps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use num_bigint::BigInt;
+    use super::*;
+
+    fn compile(source: &str) -> VirtualMachine {
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        let sources = BTreeMap::from([("test.rm".to_owned(), source.to_owned())]);
+        vm.load_sources(&PathBuf::from("test.rm"), &sources).unwrap();
+        vm
+    }
+
+    fn diff(left: &str, right: &str, criterion: DiffCriterion) -> DiffResult {
+        let mut left = compile(left);
+        left.memory.write(1, BigInt::from(3));
+        left.tapes.set_input(vec![BigInt::from(4), BigInt::from(5)]);
+        let mut right = compile(right);
+        start_together(&mut left, &mut right);
+        run_lockstep(&mut left, &mut right, &criterion, 100)
+    }
+
+    fn diverged_at(result: DiffResult) -> (u64, Divergence) {
+        match result {
+            DiffResult::Diverged { step, divergence, .. } => (step, divergence),
+            result => panic!("expected a divergence, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_first_different_accumulator() {
+        let result = diff("LOAD 1\nADD #1\nMUL #2\nEND\n", "LOAD 1\nMUL #2\nADD #1\nEND\n", DiffCriterion::Accumulator);
+        assert_eq!(diverged_at(result), (2, Divergence::Accumulator));
+    }
+
+    #[test]
+    fn outputs_only_compare_the_result() {
+        let left = "LOAD 1\nSTORE 2\nADD #1\nMUL #2\nSTORE 2\nEND\n";
+        let right = "LOAD 1\nMUL #2\nADD #2\nSTORE 2\nEND\n";
+        assert_eq!(diff(left, right, DiffCriterion::Outputs(vec![2])), DiffResult::Equal { steps: 6 });
+        assert_eq!(diverged_at(diff(left, right, DiffCriterion::Registers(vec![2]))), (2, Divergence::Register(2)));
+        let wrong = "LOAD 1\nMUL #2\nADD #3\nSTORE 2\nEND\n";
+        assert_eq!(diverged_at(diff(left, wrong, DiffCriterion::Outputs(vec![1, 2]))), (6, Divergence::Register(2)));
+    }
+
+    #[test]
+    fn output_tapes_run_on_the_same_input() {
+        let left = "READ\nWRITE\nREAD\nWRITE\nEND\n";
+        assert_eq!(diff(left, left, DiffCriterion::OutputTape), DiffResult::Equal { steps: 5 });
+        let right = "READ\nWRITE\nREAD\nADD #1\nWRITE\nEND\n";
+        assert_eq!(diverged_at(diff(left, right, DiffCriterion::OutputTape)), (5, Divergence::OutputTape(1)));
+    }
+
+    #[test]
+    fn starts_both_programs_from_the_beginning() {
+        let mut left = compile("READ\nADD 1\nWRITE\nEND\n");
+        left.memory.write(1, BigInt::from(3));
+        left.tapes.set_input(vec![BigInt::from(4)]);
+        left.step().unwrap();
+        let mut right = compile("READ\nADD 1\nWRITE\nEND\n");
+        right.step().unwrap_err();
+        start_together(&mut left, &mut right);
+        assert_eq!(run_lockstep(&mut left, &mut right, &DiffCriterion::Accumulator, 100), DiffResult::Equal { steps: 4 });
+        assert_eq!(left.tapes.output, vec![BigInt::from(7)]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::savestate::SaveStateError;
+use crate::vm::{CompileError, Diagnostics, ExecutionError, ReconfigureError, RuntimeError};
+
+/// The error behind a `CommandError`, if it came from the virtual machine
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum ErrorSource {
+    Compile(CompileError),
+    Runtime(RuntimeError),
+    SaveState(SaveStateError),
+}
+
+/// Error returned by every Tauri command
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CommandError {
+    pub message: String,
+    /// The place in the program the error refers to, if any
+    pub location: Option<Diagnostics>,
+    pub source: Option<ErrorSource>,
+}
+
+impl CommandError {
+    pub fn io(path: &str, error: std::io::Error) -> CommandError {
+        CommandError {
+            message: format!("{}: {}", path, error),
+            location: None,
+            source: None,
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError {
+            message,
+            location: None,
+            source: None,
+        }
+    }
+}
+
+impl From<CompileError> for CommandError {
+    fn from(error: CompileError) -> Self {
+        CommandError {
+            message: error.to_string(),
+            location: Some(error.location()),
+            source: Some(ErrorSource::Compile(error)),
+        }
+    }
+}
+
+impl From<RuntimeError> for CommandError {
+    fn from(error: RuntimeError) -> Self {
+        CommandError {
+            message: error.message.clone(),
+            location: error.context.as_ref().map(|context| context.line.clone()),
+            source: Some(ErrorSource::Runtime(error)),
+        }
+    }
+}
+
+impl From<SaveStateError> for CommandError {
+    fn from(error: SaveStateError) -> Self {
+        CommandError {
+            message: error.to_string(),
+            location: match &error {
+                SaveStateError::Compile(error) => Some(error.location()),
+                _ => None,
+            },
+            source: Some(ErrorSource::SaveState(error)),
+        }
+    }
+}
+
+impl From<ExecutionError> for CommandError {
+    fn from(error: ExecutionError) -> Self {
+        RuntimeError::from(error).into()
+    }
+}
+
+impl From<ReconfigureError> for CommandError {
+    fn from(error: ReconfigureError) -> Self {
+        let message = error.to_string();
+        let error: CommandError = match error {
+            ReconfigureError::Compile(error) => error.into(),
+            ReconfigureError::InvalidValue => ExecutionError::InvalidValue.into(),
+            ReconfigureError::RegisterOutOfRange => ExecutionError::RegisterOutOfRange.into(),
+        };
+        CommandError { message, ..error }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use num_bigint::BigInt;
+use crate::breakpoints::RegisterAccess;
+use crate::cost::Cost;
+
+/// Default memory budget of the undo log in bytes
+pub const DEFAULT_HISTORY_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Everything needed to undo a single step
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoEntry {
+    pub line_ptr: u32,
+    pub accumulator: BigInt,
+    /// Previous register values, `None` if the register was untouched
+    pub registers: Vec<(usize, Option<BigInt>)>,
+    pub accesses: Vec<RegisterAccess>,
+    /// Previous call stack, only for steps that changed it
+    pub call_stack: Option<Vec<u32>>,
+    /// Previous input position and output length, only for steps that used the tapes
+    pub tapes: Option<(usize, usize)>,
+    pub cost: Cost,
+}
+
+impl UndoEntry {
+    fn size(&self) -> usize {
+        let values: usize = self.registers.iter()
+            .map(|(_, value)| value.as_ref().map_or(0, |v| v.bits() as usize / 8))
+            .sum();
+        size_of::<UndoEntry>()
+            + self.accumulator.bits() as usize / 8
+            + self.registers.len() * size_of::<(usize, Option<BigInt>)>()
+            + values
+            + self.accesses.len() * size_of::<RegisterAccess>()
+            + self.call_stack.as_ref().map_or(0, |stack| stack.len() * size_of::<u32>())
+    }
+}
+
+/// Undo log of executed steps, the oldest steps are dropped once the budget is exceeded
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    entries: VecDeque<UndoEntry>,
+    pending: Option<UndoEntry>,
+    /// Upper bound for the memory used by the undo log in bytes, 0 disables it
+    pub budget: usize,
+    used: usize,
+    /// Number of steps executed so far
+    pub step: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: VecDeque::new(),
+            pending: None,
+            budget: DEFAULT_HISTORY_BUDGET,
+            used: 0,
+            step: 0,
+        }
+    }
+}
+
+impl History {
+    /// The oldest step that can still be reached by stepping back
+    pub fn oldest_step(&self) -> u64 {
+        self.step - self.entries.len() as u64
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending = None;
+        self.used = 0;
+        self.step = 0;
+    }
+
+    /// Forgets all undo entries but keeps counting steps, for changes that can't be undone
+    pub fn drop_entries(&mut self) {
+        self.entries.clear();
+        self.pending = None;
+        self.used = 0;
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.trim();
+    }
+
+    /// Starts recording the step about to be executed
+    pub fn begin(&mut self, line_ptr: u32, accumulator: &BigInt, cost: Cost) {
+        self.pending = if self.budget > 0 {
+            Some(UndoEntry {
+                line_ptr,
+                accumulator: accumulator.clone(),
+                registers: Vec::new(),
+                accesses: Vec::new(),
+                call_stack: None,
+                tapes: None,
+                cost,
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Remembers the value of a register before the running step touches it
+    pub fn record(&mut self, register: usize, previous: Option<BigInt>) {
+        if let Some(entry) = &mut self.pending {
+            if !entry.registers.iter().any(|(r, _)| *r == register) {
+                entry.registers.push((register, previous));
+            }
+        }
+    }
+
+    /// Remembers the call stack before the running step changes it
+    pub fn record_call_stack(&mut self, call_stack: &[u32]) {
+        if let Some(entry) = &mut self.pending {
+            if entry.call_stack.is_none() {
+                entry.call_stack = Some(call_stack.to_vec());
+            }
+        }
+    }
+
+    /// Remembers the tape positions before the running step moves them
+    pub fn record_tapes(&mut self, position: usize, output_length: usize) {
+        if let Some(entry) = &mut self.pending {
+            entry.tapes.get_or_insert((position, output_length));
+        }
+    }
+
+    /// Finishes the running step
+    pub fn commit(&mut self, accesses: &[RegisterAccess]) {
+        self.step += 1;
+        if let Some(mut entry) = self.pending.take() {
+            entry.accesses = accesses.to_vec();
+            self.used += entry.size();
+            self.entries.push_back(entry);
+            self.trim();
+        }
+    }
+
+    /// Drops the running step, it didn't change the machine
+    pub fn discard(&mut self) {
+        self.pending = None;
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        let entry = self.entries.pop_back()?;
+        self.used -= entry.size();
+        self.step -= 1;
+        Some(entry)
+    }
+
+    fn trim(&mut self) {
+        while self.used > self.budget {
+            match self.entries.pop_front() {
+                Some(entry) => self.used -= entry.size(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use crate::memory::Registers;
+    use crate::vm::{ExecutionResult, Snapshot, VirtualMachine};
+
+    fn compile(source: &str) -> VirtualMachine {
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        let sources = BTreeMap::from([("test.rm".to_owned(), source.to_owned())]);
+        vm.load_sources(&PathBuf::from("test.rm"), &sources).unwrap();
+        vm
+    }
+
+    /// Snapshots after every step until END
+    fn run_recording(vm: &mut VirtualMachine) -> Vec<Snapshot> {
+        let mut snapshots = vec![vm.snapshot()];
+        while let ExecutionResult::Executed { .. } = vm.step().unwrap() {
+            snapshots.push(vm.snapshot());
+        }
+        snapshots
+    }
+
+    #[test]
+    fn stepping_back_restores_every_step() {
+        let source = "READ 1\nloop: CALL twice\nREAD\nSTORE 2\nJNZERO loop\nEND\n\
+                      twice: LOAD 1\nMUL #2\nSTORE 1\nWRITE 1\nLOAD 2\nRET\n";
+        let mut vm = compile(source);
+        vm.tapes.set_input(vec![3.into(), 1.into(), 0.into()]);
+        let snapshots = run_recording(&mut vm);
+        assert_eq!(vm.tapes.output, vec![6.into(), 12.into()]);
+
+        for expected in snapshots.iter().rev().skip(1) {
+            assert_eq!(&vm.step_back().unwrap(), expected);
+        }
+        assert!(vm.step_back().is_err());
+        assert_eq!(vm.memory.touched(), BTreeMap::new());
+
+        // Running again from the start gives the same steps
+        assert_eq!(run_recording(&mut vm), snapshots);
+    }
+
+    #[test]
+    fn jumping_moves_both_ways() {
+        let mut vm = compile("LOAD #1\nloop: ADD #1\nSTORE 1\nSUB #5\nJNZERO loop\nEND\n");
+        let snapshots = run_recording(&mut vm);
+        assert_eq!(vm.jump_to_step(2).unwrap(), snapshots[2]);
+        assert_eq!(vm.jump_to_step(7).unwrap(), snapshots[7]);
+    }
+
+    #[test]
+    fn budget_limits_how_far_back() {
+        let mut vm = compile("LOAD #1\nADD #1\nADD #1\nADD #1\nEND\n");
+        vm.history.set_budget(1);
+        run_recording(&mut vm);
+        assert_eq!(vm.history.oldest_step(), 4);
+        assert!(vm.step_back().is_err());
+        assert_eq!(vm.snapshot().step, 4);
+    }
+}
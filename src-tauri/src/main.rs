@@ -2,8 +2,36 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use lazy_static::lazy_static;
-use crate::vm::{CompileError, ExecutionError, ExecutionResult};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use crate::analysis::{CompileWarning, EndCheck};
+use crate::breakpoints::{AccessKind, Breakpoint, BreakpointHit, BreakpointKind, Condition};
+use crate::coverage::CoverageFormat;
+use crate::diff::{DiffCriterion, DiffResult};
+use crate::error::CommandError;
+use crate::memory::Registers;
+use crate::profiler::ProfileEntry;
+use crate::runner::{Runner, RunnerCommand};
+use crate::sessions::{SessionEvent, SessionId, Sessions, DEFAULT_SESSION};
+use crate::trace::TraceFormat;
+use crate::vm::{ExecutionError, ExecutionResult, PcMapping, RegisterMode, RunResult, Snapshot, VirtualMachine};
 
+mod analysis;
+mod breakpoints;
+mod cost;
+mod coverage;
+mod cycles;
+mod diff;
+mod error;
+mod history;
+mod memory;
+mod profiler;
+mod runner;
+mod savestate;
+mod sessions;
+mod tapes;
+mod trace;
 mod vm;
 
 lazy_static! {
@@ -17,76 +45,502 @@ lazy_static! {
         }
     };
 
-    pub static ref VM: std::sync::Mutex<vm::VirtualMachine> = std::sync::Mutex::new(vm::VirtualMachine::new());
+    // virtual machines with their background runners
+    pub static ref SESSIONS: Mutex<Sessions> = Mutex::new(Sessions::new());
+}
+
+// A command that panicked while holding a lock must not take the others down with it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn unknown_session(id: SessionId) -> CommandError {
+    format!("Unknown session {}", id).into()
+}
+
+/// Machine of a session, commands without a session use the default one
+fn session_vm(session: Option<SessionId>) -> Result<Arc<Mutex<VirtualMachine>>, CommandError> {
+    let id = session.unwrap_or(DEFAULT_SESSION);
+    lock(&SESSIONS).vm(id).ok_or_else(|| unknown_session(id))
+}
+
+fn stop_runner(session: Option<SessionId>) -> Result<(), CommandError> {
+    let id = session.unwrap_or(DEFAULT_SESSION);
+    // Take the runner out first, stopping waits for it and must not block other sessions
+    let runner = lock(&SESSIONS).get_mut(id).ok_or_else(|| unknown_session(id))?.runner.take();
+    if let Some(runner) = runner {
+        runner.stop();
+    }
+    Ok(())
+}
+
+fn send_to_runner(session: Option<SessionId>, command: RunnerCommand) -> Result<bool, CommandError> {
+    let id = session.unwrap_or(DEFAULT_SESSION);
+    let mut sessions = lock(&SESSIONS);
+    let session = sessions.get_mut(id).ok_or_else(|| unknown_session(id))?;
+    Ok(match session.runner.as_ref() {
+        Some(runner) => runner.send(command),
+        None => false,
+    })
 }
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
+/// Creates a virtual machine independent of all others
 #[tauri::command]
-fn read_file(name: &str) -> String {
-    std::fs::read_to_string(name).unwrap()
+fn vm_create_session() -> Result<SessionId, CommandError> {
+    Ok(lock(&SESSIONS).create())
 }
 
 #[tauri::command]
-fn get_workspace() -> String {
-    WORKSPACE.clone()
+fn vm_destroy_session(session: SessionId) -> Result<(), CommandError> {
+    if session == DEFAULT_SESSION {
+        return Err("The default session can't be destroyed".to_owned().into());
+    }
+    let removed = lock(&SESSIONS).destroy(session).ok_or_else(|| unknown_session(session))?;
+    if let Some(runner) = removed.runner {
+        runner.stop();
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn vm_sessions() -> Result<Vec<SessionId>, CommandError> {
+    Ok(lock(&SESSIONS).ids())
 }
 
 #[tauri::command]
-fn vm_compile(filepath: &str) -> String {
-    println!("Compiling {}", filepath);
-    let mut vm = VM.lock().unwrap();
+fn read_file(name: &str) -> Result<String, CommandError> {
+    std::fs::read_to_string(name).map_err(|e| CommandError::io(name, e))
+}
+
+#[tauri::command]
+fn get_workspace() -> Result<String, CommandError> {
+    Ok(WORKSPACE.clone())
+}
+
+#[tauri::command]
+fn vm_compile(filepath: &str, session: Option<SessionId>) -> Result<(), CommandError> {
+    stop_runner(session)?;
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
     vm.reuse();
     let path_buf = std::path::PathBuf::from(filepath);
-    return if let Err(error) = vm.load(&path_buf) {
-        let error_str = match error {
-            CompileError::ParamError{file, line} => format!("Param error@{}:{}", file, line),
-            CompileError::LabelError{file, line} => format!("Label error@{}:{}", file, line),
-            CompileError::InvalidInstruction{file, line} => format!("Invalid Instruction@{}:{}", file, line),
-        };
-
-        error_str
-    } else {
-        "Compile OK".to_owned()
+    Ok(vm.load(&path_buf)?)
+}
+
+/// Warnings of the last compilation, e.g. paths that don't reach END
+#[tauri::command]
+fn vm_compile_warnings(session: Option<SessionId>) -> Result<Vec<CompileWarning>, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.warnings.clone())
+}
+
+/// Whether paths that don't reach END are ignored, reported as warnings or rejected
+#[tauri::command]
+fn vm_set_end_check(check: EndCheck, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.end_check = check;
+    Ok(())
+}
+
+/// Recompiles the program while keeping the machine state (edit and continue)
+#[tauri::command]
+fn vm_recompile(filepath: &str, session: Option<SessionId>) -> Result<PcMapping, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    let path_buf = std::path::PathBuf::from(filepath);
+    Ok(vm.recompile(&path_buf)?)
+}
+
+#[tauri::command]
+fn vm_step(session: Option<SessionId>) -> Result<ExecutionResult, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.step()?)
+}
+
+#[tauri::command]
+fn vm_snapshot(session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.snapshot())
+}
+
+/// Step budget for `vm_run` when the frontend doesn't pass one
+const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+#[tauri::command]
+fn vm_run(max_steps: Option<u64>, session: Option<SessionId>) -> Result<RunResult, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.run(max_steps.unwrap_or(DEFAULT_MAX_STEPS))?)
+}
+
+/// Runs the programs of two sessions from the start in lockstep on the registers and input
+/// tape of `left` until their state differs. Both run on copies, the sessions keep their state.
+#[tauri::command]
+fn vm_diff(left: SessionId, right: SessionId, criterion: DiffCriterion, max_steps: Option<u64>) -> Result<DiffResult, CommandError> {
+    let (left, right) = (session_vm(Some(left))?, session_vm(Some(right))?);
+    let mut left_vm = lock(&left).clone();
+    let mut right_vm = lock(&right).clone();
+    diff::start_together(&mut left_vm, &mut right_vm);
+    Ok(diff::run_lockstep(&mut left_vm, &mut right_vm, &criterion, max_steps.unwrap_or(DEFAULT_MAX_STEPS)))
+}
+
+/// Writes program and machine state to a JSON file, to continue the run later or elsewhere
+#[tauri::command]
+fn vm_save_state(path: &str, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let state = savestate::save(&*lock(&handle))?;
+    let json = serde_json::to_string_pretty(&state).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| CommandError::io(path, e))
+}
+
+/// Restores a state written by `vm_save_state`, returns the source files that changed since
+#[tauri::command]
+fn vm_load_state(path: &str, session: Option<SessionId>) -> Result<Vec<String>, CommandError> {
+    let json = std::fs::read_to_string(path).map_err(|e| CommandError::io(path, e))?;
+    let state: savestate::SavedState = serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))?;
+    stop_runner(session)?;
+    let handle = session_vm(session)?;
+    savestate::restore(&mut lock(&handle), &state)?;
+    Ok(savestate::changed_sources(&state))
+}
+
+#[tauri::command]
+fn vm_start(window: tauri::Window, delay_ms: u64, paused: bool, session: Option<SessionId>) -> Result<(), CommandError> {
+    stop_runner(session)?;
+    let id = session.unwrap_or(DEFAULT_SESSION);
+    let runner = Runner::start(session_vm(session)?, Duration::from_millis(delay_ms), paused, move |event| {
+        let _ = window.emit("vm-event", SessionEvent { session: id, event });
+    });
+    lock(&SESSIONS).get_mut(id).ok_or_else(|| unknown_session(id))?.runner = Some(runner);
+    Ok(())
+}
+
+#[tauri::command]
+fn vm_pause(session: Option<SessionId>) -> Result<bool, CommandError> {
+    send_to_runner(session, RunnerCommand::Pause)
+}
+
+#[tauri::command]
+fn vm_resume(session: Option<SessionId>) -> Result<bool, CommandError> {
+    send_to_runner(session, RunnerCommand::Resume)
+}
+
+#[tauri::command]
+fn vm_runner_step(session: Option<SessionId>) -> Result<bool, CommandError> {
+    send_to_runner(session, RunnerCommand::Step)
+}
+
+#[tauri::command]
+fn vm_set_delay(delay_ms: u64, session: Option<SessionId>) -> Result<bool, CommandError> {
+    send_to_runner(session, RunnerCommand::SetDelay(Duration::from_millis(delay_ms)))
+}
+
+#[tauri::command]
+fn vm_stop(session: Option<SessionId>) -> Result<(), CommandError> {
+    stop_runner(session)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn vm_set_register(register: usize, value: String, session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    let value = vm.parse_value(&value).ok_or(ExecutionError::InvalidValue)?;
+    vm.set_register(register, value)?;
+    Ok(vm.snapshot())
+}
+
+#[tauri::command]
+fn vm_set_accumulator(value: String, session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    let value = vm.parse_value(&value).ok_or(ExecutionError::InvalidValue)?;
+    vm.set_accumulator(value)?;
+    Ok(vm.snapshot())
+}
+
+#[tauri::command]
+fn vm_move_to_line(file: String, line: u32, session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.move_to_line(&file, line)?;
+    Ok(vm.snapshot())
+}
+
+/// Restarts the compiled program, registers have to be uploaded again
+#[tauri::command]
+fn vm_reset(session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    stop_runner(session)?;
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.reset();
+    Ok(vm.snapshot())
+}
+
+#[tauri::command]
+fn vm_step_over(max_steps: Option<u64>, session: Option<SessionId>) -> Result<RunResult, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.step_over(max_steps.unwrap_or(DEFAULT_MAX_STEPS))?)
+}
+
+#[tauri::command]
+fn vm_step_out(max_steps: Option<u64>, session: Option<SessionId>) -> Result<RunResult, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.step_out(max_steps.unwrap_or(DEFAULT_MAX_STEPS))?)
+}
+
+#[tauri::command]
+fn vm_run_to_line(file: String, line: u32, max_steps: Option<u64>, session: Option<SessionId>) -> Result<RunResult, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.run_to_line(file, line, max_steps.unwrap_or(DEFAULT_MAX_STEPS))?)
+}
+
+#[tauri::command]
+fn vm_step_back(session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.step_back()?)
+}
+
+#[tauri::command]
+fn vm_run_back(session: Option<SessionId>) -> Result<(Snapshot, Option<BreakpointHit>), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.run_back()?)
+}
+
+#[tauri::command]
+fn vm_jump_to_step(step: u64, session: Option<SessionId>) -> Result<Snapshot, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.jump_to_step(step)?)
+}
+
+/// Memory budget of the undo log in bytes, 0 disables reverse stepping
+#[tauri::command]
+fn vm_set_history_budget(budget: usize, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.history.set_budget(budget);
+    Ok(())
+}
+
+/// Sets the per-opcode weights of the weighted cost, missing opcodes weigh 1
+#[tauri::command]
+fn vm_set_cost_weights(weights: HashMap<String, u64>, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.cost_model.set_weights(weights)?)
+}
+
+#[tauri::command]
+fn vm_cost_weights(session: Option<SessionId>) -> Result<HashMap<String, u64>, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.cost_model.weights().clone())
+}
+
+/// Execution counters of every instruction, for the editor heatmap
+#[tauri::command]
+fn vm_profile(session: Option<SessionId>) -> Result<Vec<ProfileEntry>, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.profiler.entries(&vm.lines))
+}
+
+/// Coverage of all runs since the last `vm_clear_coverage`
+#[tauri::command]
+fn vm_coverage(format: CoverageFormat, session: Option<SessionId>) -> Result<String, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.coverage.export(format)?)
+}
+
+#[tauri::command]
+fn vm_clear_coverage(session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.coverage.clear();
+    // Keep the loaded program in the report
+    let lines = vm.lines.clone();
+    vm.coverage.register(&lines);
+    Ok(())
+}
+
+/// Stops with `NonTermination` as soon as the machine repeats a state
+#[tauri::command]
+fn vm_set_loop_detection(enabled: bool, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.cycles.enabled = enabled;
+    vm.cycles.clear();
+    Ok(())
+}
+
+/// Starts or stops recording executed instructions
+#[tauri::command]
+fn vm_set_trace(enabled: bool, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.trace.enabled = enabled;
+    Ok(())
+}
+
+#[tauri::command]
+fn vm_clear_trace(session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.trace.clear();
+    Ok(())
+}
+
+#[tauri::command]
+fn vm_export_trace(format: TraceFormat, session: Option<SessionId>) -> Result<String, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.trace.export(format)?)
+}
+
+#[tauri::command]
+fn vm_set_breakpoint(file: String, line: u32, condition: Option<String>, hit_count: Option<u32>, log_message: Option<String>, session: Option<SessionId>) -> Result<u32, CommandError> {
+    let condition = match condition {
+        Some(text) => Some(Condition::parse(&text).ok_or(format!("Invalid condition {}", text))?),
+        None => None,
     };
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.breakpoints.add(BreakpointKind::Line { file, line }, condition, hit_count, log_message))
+}
+
+#[tauri::command]
+fn vm_set_watchpoint(register: usize, access: Option<AccessKind>, condition: Option<String>, hit_count: Option<u32>, log_message: Option<String>, session: Option<SessionId>) -> Result<u32, CommandError> {
+    let condition = match condition {
+        Some(text) => Some(Condition::parse(&text).ok_or(format!("Invalid condition {}", text))?),
+        None => None,
+    };
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.breakpoints.add(BreakpointKind::Watch { register, access }, condition, hit_count, log_message))
+}
+
+#[tauri::command]
+fn vm_clear_breakpoint(id: u32, session: Option<SessionId>) -> Result<bool, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.breakpoints.remove(id))
+}
+
+#[tauri::command]
+fn vm_clear_breakpoints(session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    vm.breakpoints.clear();
+    Ok(())
 }
 
 #[tauri::command]
-fn vm_step() -> Result<ExecutionResult, ExecutionError> {
-    let mut vm = VM.lock().unwrap();
-    let a = vm.step();
-    println!("{:?}", a);
+fn vm_list_breakpoints(session: Option<SessionId>) -> Result<Vec<Breakpoint>, CommandError> {
+    let handle = session_vm(session)?;
+    let vm = lock(&handle);
+    Ok(vm.breakpoints.breakpoints.clone())
+}
 
-    a
+/// Messages recorded by logpoints since the last call
+#[tauri::command]
+fn vm_breakpoint_log(session: Option<SessionId>) -> Result<Vec<String>, CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.breakpoints.messages.drain(..).collect())
 }
 
 #[tauri::command]
-fn vm_upload(numbers: Vec<String>){
-    let mut vm = VM.lock().unwrap();
-    println!("Uploading {:?} to VM", numbers);
+fn vm_upload(numbers: Vec<String>, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
     let mut numbers_int = Vec::new();
-    for number in numbers {
-        numbers_int.push(number.parse::<u32>().unwrap());
+    for (i, number) in numbers.iter().enumerate() {
+        match vm.parse_value(number) {
+            Some(value) => numbers_int.push(value),
+            None => return Err(format!("Invalid value {} in register {} for {:?}", number, i + 1, vm.register_mode).into()),
+        }
+    }
+    vm.memory.clear();
+    for (i, value) in numbers_int.into_iter().enumerate() {
+        vm.memory.write(i + 1, value);
+    }
+    vm.cycles.clear();
+    Ok(())
+}
+
+/// Replaces the input tape, READ takes the values from the start again
+#[tauri::command]
+fn vm_set_input(values: Vec<String>, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    set_input(&mut vm, values.iter().map(String::as_str))
+}
+
+/// Loads the input tape from a file of whitespace separated numbers
+#[tauri::command]
+fn vm_load_input(path: &str, session: Option<SessionId>) -> Result<(), CommandError> {
+    let content = std::fs::read_to_string(path).map_err(|e| CommandError::io(path, e))?;
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    set_input(&mut vm, content.split_whitespace())
+}
+
+fn set_input<'a>(vm: &mut VirtualMachine, values: impl Iterator<Item = &'a str>) -> Result<(), CommandError> {
+    let mut input = Vec::new();
+    for (i, value) in values.enumerate() {
+        match vm.parse_value(value) {
+            Some(value) => input.push(value),
+            None => return Err(format!("Invalid value {} at input position {} for {:?}", value, i + 1, vm.register_mode).into()),
+        }
     }
-    vm.memory = numbers_int;
+    vm.tapes.set_input(input);
+    vm.cycles.clear();
+    Ok(())
+}
+
+#[tauri::command]
+fn vm_set_register_mode(mode: RegisterMode, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.set_register_mode(mode)?)
+}
+
+#[tauri::command]
+fn vm_set_max_register(max: Option<usize>, session: Option<SessionId>) -> Result<(), CommandError> {
+    let handle = session_vm(session)?;
+    let mut vm = lock(&handle);
+    Ok(vm.set_max_register(max)?)
 }
 
 #[tauri::command]
-fn list_files(basepath: &str) -> Vec<String> {
+fn list_files(basepath: &str) -> Result<Vec<String>, CommandError> {
     // List all files in a directory recursively
     // Return the full file names
     let mut files = Vec::new();
-    for entry in std::fs::read_dir(basepath).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
+    for entry in std::fs::read_dir(basepath).map_err(|e| CommandError::io(basepath, e))? {
+        let path = entry.map_err(|e| CommandError::io(basepath, e))?.path();
+        let name = path.to_string_lossy().into_owned();
         if path.is_dir() {
-            files.push(path.to_str().unwrap().to_owned() + "/");
-            files.append(&mut list_files(path.to_str().unwrap()));
+            files.push(name.clone() + "/");
+            files.append(&mut list_files(&name)?);
         } else {
-            files.push(path.to_str().unwrap().to_string());
+            files.push(name);
         }
     }
 
-    return files;
+    return Ok(files);
 }
 
 fn main() {
@@ -97,9 +551,56 @@ fn main() {
             read_file,
             list_files,
             get_workspace,
+            vm_create_session,
+            vm_destroy_session,
+            vm_sessions,
             vm_compile,
+            vm_recompile,
+            vm_compile_warnings,
+            vm_set_end_check,
             vm_step,
-            vm_upload
+            vm_run,
+            vm_diff,
+            vm_save_state,
+            vm_load_state,
+            vm_snapshot,
+            vm_start,
+            vm_pause,
+            vm_resume,
+            vm_runner_step,
+            vm_set_delay,
+            vm_stop,
+            vm_set_register,
+            vm_set_accumulator,
+            vm_move_to_line,
+            vm_reset,
+            vm_step_over,
+            vm_step_out,
+            vm_run_to_line,
+            vm_step_back,
+            vm_run_back,
+            vm_jump_to_step,
+            vm_set_history_budget,
+            vm_set_cost_weights,
+            vm_cost_weights,
+            vm_profile,
+            vm_coverage,
+            vm_clear_coverage,
+            vm_set_loop_detection,
+            vm_set_trace,
+            vm_clear_trace,
+            vm_export_trace,
+            vm_set_breakpoint,
+            vm_set_watchpoint,
+            vm_clear_breakpoint,
+            vm_clear_breakpoints,
+            vm_list_breakpoints,
+            vm_breakpoint_log,
+            vm_upload,
+            vm_set_input,
+            vm_load_input,
+            vm_set_register_mode,
+            vm_set_max_register
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
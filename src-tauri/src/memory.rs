@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use num_bigint::BigInt;
+use num_traits::Zero;
+
+/// Number of a register operand, register numbers start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RegisterIndex(NonZeroUsize);
+
+impl RegisterIndex {
+    /// `None` for register 0, which doesn't exist
+    pub fn new(index: usize) -> Option<RegisterIndex> {
+        NonZeroUsize::new(index).map(RegisterIndex)
+    }
+
+    pub fn get(self) -> usize {
+        self.0.get()
+    }
+}
+
+impl std::fmt::Display for RegisterIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Storage for the registers of a virtual machine. Register numbers start at 1,
+/// registers that were never touched hold 0.
+pub trait Registers: std::fmt::Debug + Clone + PartialEq + Default + Send {
+    /// Reads a register and marks it as touched
+    fn read(&mut self, index: usize) -> BigInt;
+    /// Reads a register without marking it as touched
+    fn peek(&self, index: usize) -> BigInt;
+    /// The value of a register, `None` if it was never touched
+    fn get(&self, index: usize) -> Option<BigInt>;
+    fn write(&mut self, index: usize, value: BigInt);
+    /// Marks a register as untouched again
+    fn remove(&mut self, index: usize);
+    /// All registers that were read or written, ordered by register number
+    fn touched(&self) -> BTreeMap<usize, BigInt>;
+    fn clear(&mut self);
+}
+
+/// Register file that only stores touched registers, so large pointers don't allocate
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SparseRegisters {
+    registers: BTreeMap<usize, BigInt>,
+}
+
+impl Registers for SparseRegisters {
+    fn read(&mut self, index: usize) -> BigInt {
+        self.registers.entry(index).or_insert_with(BigInt::zero).clone()
+    }
+
+    fn peek(&self, index: usize) -> BigInt {
+        self.registers.get(&index).cloned().unwrap_or_else(BigInt::zero)
+    }
+
+    fn get(&self, index: usize) -> Option<BigInt> {
+        self.registers.get(&index).cloned()
+    }
+
+    fn write(&mut self, index: usize, value: BigInt) {
+        self.registers.insert(index, value);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.registers.remove(&index);
+    }
+
+    fn touched(&self) -> BTreeMap<usize, BigInt> {
+        self.registers.clone()
+    }
+
+    fn clear(&mut self) {
+        self.registers.clear();
+    }
+}
@@ -0,0 +1,77 @@
+use crate::cost::Cost;
+use crate::vm::{Diagnostics, Line};
+
+/// Execution counters of a single compiled line
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize)]
+pub struct LineProfile {
+    pub executions: u64,
+    /// Cost of all executions of this line
+    pub cost: Cost,
+    /// Conditional jumps only: how often the jump was taken
+    pub taken: u64,
+    /// Conditional jumps only: how often execution fell through
+    pub not_taken: u64,
+}
+
+/// Profile of a line for the editor heatmap
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ProfileEntry {
+    pub line: Diagnostics,
+    pub instruction: String,
+    pub profile: LineProfile,
+}
+
+/// Counters for every compiled line, indexed like `VirtualMachine::lines`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Profiler {
+    lines: Vec<LineProfile>,
+}
+
+impl Profiler {
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Counts an execution of the line at `index`, `branch` tells whether a conditional jump was taken
+    pub fn record(&mut self, index: usize, cost: Cost, branch: Option<bool>) {
+        if index >= self.lines.len() {
+            self.lines.resize(index + 1, LineProfile::default());
+        }
+        let profile = &mut self.lines[index];
+        profile.executions += 1;
+        profile.cost.add(cost);
+        match branch {
+            Some(true) => profile.taken += 1,
+            Some(false) => profile.not_taken += 1,
+            None => {}
+        }
+    }
+
+    /// Takes back an execution recorded by `record`, for stepping backwards
+    pub fn unrecord(&mut self, index: usize, cost: Cost, branch: Option<bool>) {
+        if let Some(profile) = self.lines.get_mut(index) {
+            profile.executions = profile.executions.saturating_sub(1);
+            profile.cost = profile.cost.since(cost);
+            match branch {
+                Some(true) => profile.taken = profile.taken.saturating_sub(1),
+                Some(false) => profile.not_taken = profile.not_taken.saturating_sub(1),
+                None => {}
+            }
+        }
+    }
+
+    /// Profiles of all lines holding an instruction
+    pub fn entries(&self, lines: &[Line]) -> Vec<ProfileEntry> {
+        lines.iter().enumerate()
+            .filter(|(_, line)| line.instruction.is_some())
+            .map(|(index, line)| ProfileEntry {
+                line: Diagnostics {
+                    line: line.line_number,
+                    file: line.file_name.clone(),
+                },
+                instruction: line.line.clone(),
+                profile: self.lines.get(index).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::breakpoints::BreakpointHit;
+use crate::memory::Registers;
+use crate::vm::{ExecutionResult, RuntimeError, Snapshot, VirtualMachine};
+
+/// Minimum time between progress events when running without delay
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunnerCommand {
+    Pause,
+    Resume,
+    Stop,
+    /// Executes a single step, only while paused
+    Step,
+    SetDelay(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum RunnerEvent {
+    /// A single executed step, sent for every step when running with a delay
+    Step(ExecutionResult),
+    /// Full state, sent periodically when running without delay
+    Progress(Snapshot),
+    Paused(Snapshot),
+    Breakpoint(BreakpointHit, Snapshot),
+    /// Message recorded by a logpoint
+    Log(String),
+    /// Reached END, with the final state as progress events may have skipped the last changes
+    Finished(ExecutionResult, Snapshot),
+    Failed(RuntimeError),
+    Stopped,
+}
+
+/// Executes a virtual machine on a background thread. The machine is only locked
+/// for the duration of a single step, so other commands can access it in between.
+pub struct Runner {
+    sender: Sender<RunnerCommand>,
+    handle: JoinHandle<()>,
+}
+
+impl Runner {
+    pub fn start<M, F>(vm: Arc<Mutex<VirtualMachine<M>>>, delay: Duration, paused: bool, on_event: F) -> Runner
+    where
+        M: Registers + 'static,
+        F: Fn(RunnerEvent) + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let handle = std::thread::spawn(move || run_loop(&vm, receiver, delay, paused, on_event));
+
+        Runner { sender, handle }
+    }
+
+    /// Sends a command to the runner, returns false if it already finished
+    pub fn send(&self, command: RunnerCommand) -> bool {
+        self.sender.send(command).is_ok()
+    }
+
+    /// Stops the runner and waits for the thread to exit
+    pub fn stop(self) {
+        let _ = self.sender.send(RunnerCommand::Stop);
+        let _ = self.handle.join();
+    }
+}
+
+fn run_loop<M, F>(vm: &Mutex<VirtualMachine<M>>, receiver: Receiver<RunnerCommand>, mut delay: Duration, mut paused: bool, on_event: F)
+where
+    M: Registers,
+    F: Fn(RunnerEvent),
+{
+    let mut last_progress = Instant::now();
+
+    if paused {
+        on_event(RunnerEvent::Paused(vm.lock().unwrap().snapshot()));
+    }
+
+    loop {
+        // Block while paused, otherwise wait out the delay while listening for commands
+        let command = if paused {
+            match receiver.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            }
+        } else {
+            match receiver.recv_timeout(delay) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        };
+
+        let mut single_step = false;
+        match command {
+            Some(RunnerCommand::Pause) => {
+                paused = true;
+                on_event(RunnerEvent::Paused(vm.lock().unwrap().snapshot()));
+                continue;
+            }
+            Some(RunnerCommand::Resume) => {
+                paused = false;
+                continue;
+            }
+            Some(RunnerCommand::Stop) => {
+                on_event(RunnerEvent::Stopped);
+                return;
+            }
+            Some(RunnerCommand::Step) => {
+                if !paused {
+                    continue;
+                }
+                single_step = true;
+            }
+            Some(RunnerCommand::SetDelay(new_delay)) => {
+                delay = new_delay;
+                continue;
+            }
+            None => {}
+        }
+
+        let mut vm = vm.lock().unwrap();
+        if !single_step {
+            let hit = vm.check_line_breakpoints();
+            emit_log(&mut vm, &on_event);
+            if let Some(hit) = hit {
+                paused = true;
+                on_event(RunnerEvent::Breakpoint(hit, vm.snapshot()));
+                continue;
+            }
+        }
+
+        let result = vm.step();
+        let hit = vm.check_watchpoints();
+        emit_log(&mut vm, &on_event);
+        if let (Ok(ExecutionResult::Executed { .. }), Some(hit)) = (&result, hit) {
+            paused = true;
+            on_event(RunnerEvent::Breakpoint(hit, vm.snapshot()));
+            continue;
+        }
+
+        match result {
+            Ok(result @ ExecutionResult::End { .. }) => {
+                on_event(RunnerEvent::Finished(result, vm.snapshot()));
+                return;
+            }
+            Ok(result) => {
+                if single_step || !delay.is_zero() {
+                    on_event(RunnerEvent::Step(result));
+                } else if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    on_event(RunnerEvent::Progress(vm.snapshot()));
+                    last_progress = Instant::now();
+                }
+            }
+            Err(error) => {
+                on_event(RunnerEvent::Failed(error));
+                return;
+            }
+        }
+    }
+}
+
+fn emit_log<M, F>(vm: &mut VirtualMachine<M>, on_event: &F)
+where
+    M: Registers,
+    F: Fn(RunnerEvent),
+{
+    for message in vm.breakpoints.messages.drain(..) {
+        on_event(RunnerEvent::Log(message));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use num_bigint::BigInt;
+use crate::analysis::EndCheck;
+use crate::cost::Cost;
+use crate::memory::Registers;
+use crate::trace::Trace;
+use crate::vm::{CompileError, RegisterMode, VirtualMachine};
+
+/// Version written to saved states, files of other versions are rejected
+pub const SAVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SavedSource {
+    pub file: String,
+    /// FNV-1a hash of the content, to tell whether the file on disk still matches
+    pub hash: String,
+    pub content: String,
+}
+
+/// Everything needed to continue a paused run elsewhere, the program is stored as source
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SavedState {
+    pub version: u32,
+    /// Main file of the program
+    pub program: String,
+    pub sources: Vec<SavedSource>,
+    pub register_mode: RegisterMode,
+    pub max_register: Option<usize>,
+    pub end_check: EndCheck,
+    pub registers: BTreeMap<usize, String>,
+    pub accumulator: String,
+    pub line_ptr: u32,
+    pub call_stack: Vec<u32>,
+    /// Number of steps executed so far
+    pub step: u64,
+    pub cost: Cost,
+    pub trace: Trace,
+    #[serde(default)]
+    pub input: Vec<String>,
+    /// Values of the input tape taken so far
+    #[serde(default)]
+    pub input_position: usize,
+    #[serde(default)]
+    pub output: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum SaveStateError {
+    UnsupportedVersion {
+        version: u32,
+    },
+    /// No program is loaded, so there is nothing to save
+    NoProgram,
+    /// The stored program doesn't compile
+    Compile(CompileError),
+    /// Registers, accumulator or program position don't fit the program
+    InvalidState,
+}
+
+impl std::fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveStateError::UnsupportedVersion { version } => write!(f, "Unsupported save file version {}", version),
+            SaveStateError::NoProgram => write!(f, "No program loaded"),
+            SaveStateError::Compile(error) => write!(f, "Saved program doesn't compile: {}", error),
+            SaveStateError::InvalidState => write!(f, "Saved machine state doesn't fit the program"),
+        }
+    }
+}
+
+/// 64 bit FNV-1a, stable across builds unlike the standard library hasher
+pub fn source_hash(content: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+pub fn save<M: Registers>(vm: &VirtualMachine<M>) -> Result<SavedState, SaveStateError> {
+    let program = vm.program.as_ref().ok_or(SaveStateError::NoProgram)?;
+    let snapshot = vm.snapshot();
+
+    Ok(SavedState {
+        version: SAVE_VERSION,
+        program: program.to_string_lossy().into_owned(),
+        sources: vm.sources.iter().map(|(file, content)| SavedSource {
+            file: file.clone(),
+            hash: source_hash(content),
+            content: content.clone(),
+        }).collect(),
+        register_mode: vm.register_mode,
+        max_register: vm.max_register,
+        end_check: vm.end_check,
+        registers: snapshot.register,
+        accumulator: snapshot.accumulator,
+        line_ptr: snapshot.line_ptr,
+        call_stack: snapshot.call_stack,
+        step: snapshot.step,
+        cost: snapshot.cost,
+        trace: vm.trace.clone(),
+        input: vm.tapes.input.iter().map(|value| value.to_string()).collect(),
+        input_position: snapshot.input_position,
+        output: snapshot.output,
+    })
+}
+
+/// Replaces the program and state of `vm` by a saved state. Breakpoints, cost weights and
+/// coverage are kept. On error the machine is left unchanged.
+pub fn restore<M: Registers>(vm: &mut VirtualMachine<M>, state: &SavedState) -> Result<(), SaveStateError> {
+    if state.version != SAVE_VERSION {
+        return Err(SaveStateError::UnsupportedVersion { version: state.version });
+    }
+
+    let mut restored = vm.clone();
+    restored.reuse();
+    restored.register_mode = state.register_mode;
+    restored.max_register = state.max_register;
+    restored.end_check = state.end_check;
+    let sources = state.sources.iter().map(|source| (source.file.clone(), source.content.clone())).collect();
+    restored.load_sources(&PathBuf::from(&state.program), &sources).map_err(SaveStateError::Compile)?;
+
+    for (register, value) in &state.registers {
+        let value = restored.parse_value(value).ok_or(SaveStateError::InvalidState)?;
+        restored.set_register(*register, value).map_err(|_| SaveStateError::InvalidState)?;
+    }
+    let accumulator = restored.parse_value(&state.accumulator).ok_or(SaveStateError::InvalidState)?;
+    restored.set_accumulator(accumulator).map_err(|_| SaveStateError::InvalidState)?;
+
+    let parse_tape = |values: &[String]| -> Result<Vec<BigInt>, SaveStateError> {
+        values.iter().map(|value| value.parse::<BigInt>().map_err(|_| SaveStateError::InvalidState)).collect()
+    };
+    restored.tapes.input = parse_tape(&state.input)?;
+    restored.tapes.output = parse_tape(&state.output)?;
+    if state.input_position > restored.tapes.input.len() {
+        return Err(SaveStateError::InvalidState);
+    }
+    restored.tapes.position = state.input_position;
+
+    let program_length = restored.lines.len() as u32;
+    if state.line_ptr > program_length || state.call_stack.iter().any(|ptr| *ptr > program_length) {
+        return Err(SaveStateError::InvalidState);
+    }
+    restored.line_ptr = state.line_ptr;
+    restored.call_stack = state.call_stack.clone();
+    restored.history.step = state.step;
+    restored.cost = state.cost;
+    restored.trace = Trace { enabled: vm.trace.enabled, ..state.trace.clone() };
+
+    *vm = restored;
+    Ok(())
+}
+
+/// Files whose content on disk differs from the saved program, or that can't be read
+pub fn changed_sources(state: &SavedState) -> Vec<String> {
+    state.sources.iter()
+        .filter(|source| match std::fs::read_to_string(&source.file) {
+            Ok(content) => source_hash(&content) != source.hash,
+            Err(_) => true,
+        })
+        .map(|source| source.file.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paused_machine() -> VirtualMachine {
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        let sources = BTreeMap::from([
+            ("main.rm".to_owned(), "READ 1\nloop: CALL double\nJNZERO loop\nEND\n#include sub.rm\n".to_owned()),
+            ("sub.rm".to_owned(), "double: LOAD 1\nMUL #2\nSTORE 1\nWRITE\nSUB #8\nRET\n".to_owned()),
+        ]);
+        vm.load_sources(&PathBuf::from("main.rm"), &sources).unwrap();
+        vm.tapes.set_input(vec![BigInt::from(2), BigInt::from(5)]);
+        // Inside the second CALL
+        for _ in 0..11 {
+            vm.step().unwrap();
+        }
+        vm
+    }
+
+    #[test]
+    fn restores_the_saved_state() {
+        let vm = paused_machine();
+        assert_eq!(vm.call_stack.len(), 1);
+        let json = serde_json::to_string(&save(&vm).unwrap()).unwrap();
+        let state: SavedState = serde_json::from_str(&json).unwrap();
+
+        let mut restored: VirtualMachine = VirtualMachine::new();
+        restore(&mut restored, &state).unwrap();
+        assert_eq!(restored.snapshot(), vm.snapshot());
+        assert_eq!(restored.tapes, vm.tapes);
+        assert_eq!(restored.sources, vm.sources);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let state = SavedState { version: SAVE_VERSION + 1, ..save(&paused_machine()).unwrap() };
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        assert_eq!(restore(&mut vm, &state), Err(SaveStateError::UnsupportedVersion { version: SAVE_VERSION + 1 }));
+        assert_eq!(vm, VirtualMachine::new());
+    }
+
+    #[test]
+    fn rejects_position_outside_the_program() {
+        let vm = paused_machine();
+        let state = SavedState { line_ptr: vm.lines.len() as u32 + 1, ..save(&vm).unwrap() };
+        let mut target = paused_machine();
+        assert_eq!(restore(&mut target, &state), Err(SaveStateError::InvalidState));
+        assert_eq!(target, vm);
+    }
+}
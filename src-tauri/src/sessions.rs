@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use crate::runner::{Runner, RunnerEvent};
+use crate::vm::VirtualMachine;
+
+pub type SessionId = u32;
+
+/// Session used by commands that don't name one, always exists
+pub const DEFAULT_SESSION: SessionId = 0;
+
+/// A virtual machine with its own program, state and background runner
+pub struct Session {
+    pub vm: Arc<Mutex<VirtualMachine>>,
+    pub runner: Option<Runner>,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            vm: Arc::new(Mutex::new(VirtualMachine::new())),
+            runner: None,
+        }
+    }
+}
+
+/// Runner event tagged with the session it came from
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SessionEvent {
+    pub session: SessionId,
+    pub event: RunnerEvent,
+}
+
+/// All open sessions. Every machine has its own lock, so sessions only contend for
+/// this registry while being looked up.
+pub struct Sessions {
+    next_id: SessionId,
+    sessions: BTreeMap<SessionId, Session>,
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        let mut sessions = BTreeMap::new();
+        sessions.insert(DEFAULT_SESSION, Session::new());
+        Sessions {
+            next_id: DEFAULT_SESSION + 1,
+            sessions,
+        }
+    }
+
+    pub fn create(&mut self) -> SessionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sessions.insert(id, Session::new());
+        id
+    }
+
+    /// Removes a session, the caller has to stop its runner. The default session can't be removed.
+    pub fn destroy(&mut self, id: SessionId) -> Option<Session> {
+        if id == DEFAULT_SESSION {
+            return None;
+        }
+        self.sessions.remove(&id)
+    }
+
+    pub fn vm(&self, id: SessionId) -> Option<Arc<Mutex<VirtualMachine>>> {
+        self.sessions.get(&id).map(|session| session.vm.clone())
+    }
+
+    pub fn get_mut(&mut self, id: SessionId) -> Option<&mut Session> {
+        self.sessions.get_mut(&id)
+    }
+
+    pub fn ids(&self) -> Vec<SessionId> {
+        self.sessions.keys().copied().collect()
+    }
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Sessions::new()
+    }
+}
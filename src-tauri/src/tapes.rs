@@ -0,0 +1,46 @@
+use num_bigint::BigInt;
+
+/// Input and output tape of the machine. READ takes the input from left to right,
+/// WRITE appends to the output.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tapes {
+    pub input: Vec<BigInt>,
+    /// Index of the next value READ takes from `input`
+    pub position: usize,
+    pub output: Vec<BigInt>,
+}
+
+impl Tapes {
+    /// Replaces the input and starts over
+    pub fn set_input(&mut self, input: Vec<BigInt>) {
+        self.input = input;
+        self.rewind();
+    }
+
+    /// Starts reading the input from the beginning again and clears the output
+    pub fn rewind(&mut self) {
+        self.position = 0;
+        self.output.clear();
+    }
+
+    /// The value the next READ takes, `None` if the input is exhausted
+    pub fn peek(&self) -> Option<&BigInt> {
+        self.input.get(self.position)
+    }
+
+    pub fn read(&mut self) -> Option<BigInt> {
+        let value = self.input.get(self.position).cloned()?;
+        self.position += 1;
+        Some(value)
+    }
+
+    pub fn write(&mut self, value: BigInt) {
+        self.output.push(value);
+    }
+
+    /// Goes back to an earlier position, for stepping backwards
+    pub fn restore(&mut self, position: usize, output_length: usize) {
+        self.position = position;
+        self.output.truncate(output_length);
+    }
+}
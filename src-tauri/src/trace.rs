@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::breakpoints::{AccessKind, RegisterAccess};
+use crate::vm::Diagnostics;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum TraceFormat {
+    Csv,
+    Json,
+    /// Belegungstabelle as Markdown table
+    Markdown,
+    /// Belegungstabelle as LaTeX tabular
+    Latex,
+    /// Belegungstabelle as HTML table
+    Html,
+}
+
+/// A register accessed by a traced step, `value` is the content after the step
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TracedRegister {
+    pub register: usize,
+    pub kind: AccessKind,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TraceEntry {
+    pub step: u64,
+    pub line: Diagnostics,
+    pub instruction: String,
+    pub accumulator_before: String,
+    pub accumulator_after: String,
+    pub registers: Vec<TracedRegister>,
+}
+
+/// Records every executed instruction while enabled
+#[derive(Debug, Clone, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub struct Trace {
+    #[serde(skip)]
+    pub enabled: bool,
+    /// Registers before the first traced step, the first row of the Belegungstabelle
+    pub initial_registers: BTreeMap<usize, String>,
+    pub initial_accumulator: String,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn clear(&mut self) {
+        self.initial_registers.clear();
+        self.initial_accumulator = String::new();
+        self.entries.clear();
+    }
+
+    /// Remembers the state before the first step, so the table can start with it
+    pub fn start(&mut self, registers: BTreeMap<usize, String>, accumulator: String) {
+        self.initial_registers = registers;
+        self.initial_accumulator = accumulator;
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Forgets steps after `step`, e.g. after stepping back
+    pub fn truncate(&mut self, step: u64) {
+        self.entries.retain(|entry| entry.step <= step);
+    }
+
+    pub fn export(&self, format: TraceFormat) -> Result<String, String> {
+        match format {
+            TraceFormat::Csv => Ok(self.to_csv()),
+            TraceFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            TraceFormat::Markdown => Ok(self.to_markdown()),
+            TraceFormat::Latex => Ok(self.to_latex()),
+            TraceFormat::Html => Ok(self.to_html()),
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("step,file,line,instruction,accumulator_before,accumulator_after,registers\n");
+        for entry in &self.entries {
+            let registers = entry.registers.iter()
+                .map(|r| format!("{} c({})={}", access_letter(r.kind), r.register, r.value))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let fields = [
+                entry.step.to_string(),
+                entry.line.file.clone(),
+                (entry.line.line + 1).to_string(),
+                entry.instruction.clone(),
+                entry.accumulator_before.clone(),
+                entry.accumulator_after.clone(),
+                registers,
+            ];
+            csv.push_str(&fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// The Belegungstabelle: one row per step, only changed cells are filled
+    fn occupancy_table(&self) -> (Vec<usize>, Vec<Vec<String>>) {
+        let mut columns: BTreeSet<usize> = self.initial_registers.keys().copied().collect();
+        for entry in &self.entries {
+            columns.extend(entry.registers.iter().filter(|r| r.kind == AccessKind::Write).map(|r| r.register));
+        }
+        let columns: Vec<usize> = columns.into_iter().collect();
+
+        let mut rows = Vec::new();
+        let mut first = vec![String::new(), String::new(), self.initial_accumulator.clone()];
+        first.extend(columns.iter().map(|c| self.initial_registers.get(c).cloned().unwrap_or_else(|| "0".to_owned())));
+        rows.push(first);
+
+        for entry in &self.entries {
+            let mut row = vec![entry.step.to_string(), entry.instruction.clone()];
+            row.push(if entry.accumulator_before != entry.accumulator_after {
+                entry.accumulator_after.clone()
+            } else {
+                String::new()
+            });
+            row.extend(columns.iter().map(|c| {
+                entry.registers.iter()
+                    .rfind(|r| r.register == *c && r.kind == AccessKind::Write)
+                    .map(|r| r.value.clone())
+                    .unwrap_or_default()
+            }));
+            rows.push(row);
+        }
+
+        (columns, rows)
+    }
+
+    fn header(columns: &[usize]) -> Vec<String> {
+        let mut header = vec!["Schritt".to_owned(), "Befehl".to_owned(), "Akku".to_owned()];
+        header.extend(columns.iter().map(|c| format!("c({})", c)));
+        header
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let (columns, rows) = self.occupancy_table();
+        let header = Trace::header(&columns);
+        let mut markdown = format!("| {} |\n", header.join(" | "));
+        markdown.push_str(&format!("|{}\n", "---|".repeat(header.len())));
+        for row in rows {
+            let cells: Vec<String> = row.iter().enumerate()
+                .map(|(i, cell)| if i == 1 && !cell.is_empty() { format!("`{}`", cell) } else { cell.replace('|', "\\|") })
+                .collect();
+            markdown.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+        markdown
+    }
+
+    pub fn to_latex(&self) -> String {
+        let (columns, rows) = self.occupancy_table();
+        let header = Trace::header(&columns);
+        let mut latex = format!("\\begin{{tabular}}{{|{}}}\n\\hline\n", "c|".repeat(header.len()));
+        latex.push_str(&format!("{} \\\\\n\\hline\n", header.join(" & ")));
+        for row in rows {
+            let cells: Vec<String> = row.iter().map(|cell| latex_escape(cell)).collect();
+            latex.push_str(&format!("{} \\\\\n", cells.join(" & ")));
+        }
+        latex.push_str("\\hline\n\\end{tabular}\n");
+        latex
+    }
+
+    pub fn to_html(&self) -> String {
+        let (columns, rows) = self.occupancy_table();
+        let header = Trace::header(&columns);
+        let mut html = String::from("<table>\n<thead>\n<tr>");
+        for cell in header {
+            html.push_str(&format!("<th>{}</th>", html_escape(&cell)));
+        }
+        html.push_str("</tr>\n</thead>\n<tbody>\n");
+        for row in rows {
+            html.push_str("<tr>");
+            for cell in row {
+                html.push_str(&format!("<td>{}</td>", html_escape(&cell)));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</tbody>\n</table>\n");
+        html
+    }
+}
+
+/// Builds the accessed registers of a step from the recorded accesses
+pub fn traced_registers(accesses: &[RegisterAccess], value: impl Fn(usize) -> String) -> Vec<TracedRegister> {
+    accesses.iter()
+        .map(|a| TracedRegister { register: a.register, kind: a.kind, value: value(a.register) })
+        .collect()
+}
+
+fn access_letter(kind: AccessKind) -> &'static str {
+    match kind {
+        AccessKind::Read => "R",
+        AccessKind::Write => "W",
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn latex_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '*' => escaped.push_str("$\\ast$"),
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
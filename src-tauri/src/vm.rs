@@ -1,16 +1,35 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf};
+use crate::analysis::{check_end, CompileWarning, EndCheck};
+use crate::breakpoints::{same_file, AccessKind, BreakpointHit, BreakpointKind, Breakpoints, RegisterAccess};
+use crate::cost::{Cost, CostModel};
+use crate::coverage::Coverage;
+use crate::cycles::{hash_state, CycleDetector};
+use crate::history::History;
+use crate::memory::{RegisterIndex, Registers, SparseRegisters};
+use crate::profiler::Profiler;
+use crate::tapes::Tapes;
+use crate::trace::{traced_registers, Trace, TraceEntry};
+use num_bigint::{BigInt, Sign};
+use num_traits::{One, ToPrimitive, Zero};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum PtrType {
-    Immediate(i32),
-    Register(i32),
-    Pointer(i32),
+    Immediate(BigInt),
+    Register(RegisterIndex),
+    Pointer(RegisterIndex),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RefPtrType {
-    Register(i32),
-    Pointer(i32),
+    Register(RegisterIndex),
+    Pointer(RegisterIndex),
+}
+
+/// Jump target, the label is resolved to an index into `lines` when linking
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jump {
+    pub label: String,
+    pub target: u32,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -21,12 +40,42 @@ pub enum Instruction {
     Sub(PtrType),
     Div(PtrType),
     Mul(PtrType),
-    Goto(String),
-    JumpIfZero(String),
-    JumpIfNotZero(String),
+    Goto(Jump),
+    JumpIfZero(Jump),
+    JumpIfNotZero(Jump),
+    Call(Jump),
+    Return(),
+    /// Takes the next value of the input tape into a register, or the accumulator if `None`
+    Read(Option<RefPtrType>),
+    /// Appends a value to the output tape, the accumulator if `None`
+    Write(Option<PtrType>),
     End(),
 }
 
+/// Mnemonics of all instructions, as used by `Instruction::opcode`
+pub const OPCODES: [&str; 14] = ["LOAD", "STORE", "ADD", "SUB", "DIV", "MUL", "GOTO", "JZERO", "JNZERO", "CALL", "RET", "READ", "WRITE", "END"];
+
+impl Instruction {
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            Instruction::Load(_) => "LOAD",
+            Instruction::Store(_) => "STORE",
+            Instruction::Add(_) => "ADD",
+            Instruction::Sub(_) => "SUB",
+            Instruction::Div(_) => "DIV",
+            Instruction::Mul(_) => "MUL",
+            Instruction::Goto(_) => "GOTO",
+            Instruction::JumpIfZero(_) => "JZERO",
+            Instruction::JumpIfNotZero(_) => "JNZERO",
+            Instruction::Call(_) => "CALL",
+            Instruction::Return() => "RET",
+            Instruction::Read(_) => "READ",
+            Instruction::Write(_) => "WRITE",
+            Instruction::End() => "END",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub line: String,
@@ -36,17 +85,77 @@ pub struct Line {
     pub label: Option<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum WordWidth {
+    W8,
+    W16,
+    W32,
+    W64,
+}
+
+impl WordWidth {
+    pub fn bits(&self) -> u32 {
+        match self {
+            WordWidth::W8 => 8,
+            WordWidth::W16 => 16,
+            WordWidth::W32 => 32,
+            WordWidth::W64 => 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum RegisterMode {
+    /// 32 bit unsigned registers as in the original machine, SUB leaves the accumulator
+    /// unchanged if the result would be negative
+    Standard,
+    /// Fixed width unsigned registers that wrap around on overflow
+    Unsigned(WordWidth),
+    /// Fixed width two's complement registers that wrap around on overflow
+    Signed(WordWidth),
+    /// Unbounded natural numbers, like the theoretical register machine
+    Natural,
+    /// Unbounded signed integers
+    Integer,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct VirtualMachine {
-    pub memory: Vec<u32>,
-    pub accumulator: u32,
+pub struct VirtualMachine<M: Registers = SparseRegisters> {
+    pub memory: M,
+    pub accumulator: BigInt,
+    pub register_mode: RegisterMode,
+    pub max_register: Option<usize>,
     pub lines: Vec<Line>,
     pub line_ptr: u32,
+    /// Return addresses of the active CALLs
+    pub call_stack: Vec<u32>,
     pub defines: HashMap<String, String>,
-    pub labels: Vec<String>,
+    pub labels: HashMap<String, u32>,
+    pub breakpoints: Breakpoints,
+    /// Registers accessed by the last step
+    pub accesses: Vec<RegisterAccess>,
+    pub history: History,
+    pub trace: Trace,
+    /// Cost of all steps executed so far
+    pub cost: Cost,
+    pub cost_model: CostModel,
+    pub profiler: Profiler,
+    /// Kept across runs until cleared explicitly
+    pub coverage: Coverage,
+    pub cycles: CycleDetector,
+    pub tapes: Tapes,
+    pub end_check: EndCheck,
+    /// Warnings of the last compilation
+    pub warnings: Vec<CompileWarning>,
+    /// Main file of the loaded program
+    pub program: Option<PathBuf>,
+    /// Content of every compiled file by name, so saved states carry the program
+    pub sources: BTreeMap<String, String>,
+    // line_ptr of the line breakpoint we stopped at, so continuing doesn't stop again
+    stopped_at: Option<u32>,
 }
 
-#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Diagnostics {
     pub line: u32,
     pub file: String,
@@ -66,6 +175,76 @@ pub enum CompileError {
         file: String,
         line: u32,
     },
+    ValueOutOfRange {
+        file: String,
+        line: u32,
+    },
+    /// Register 0 or a register beyond the configured maximum
+    InvalidRegister {
+        file: String,
+        line: u32,
+    },
+    /// A path through the program doesn't reach END, only with `EndCheck::Error`
+    EndNotReached {
+        file: String,
+        line: u32,
+    },
+    /// `#define` or `#include` without the required arguments
+    InvalidDirective {
+        file: String,
+        line: u32,
+    },
+    /// A file includes itself, directly or through other files
+    RecursiveInclude {
+        file: String,
+        line: u32,
+    },
+    /// A source file couldn't be read, `line` is the `#include` or 0 for the main file
+    FileError {
+        file: String,
+        line: u32,
+        message: String,
+    },
+    /// Recompiling removed the place a running CALL returns to, `line` is in the old program
+    ReturnAddressLost {
+        file: String,
+        line: u32,
+    },
+}
+
+impl CompileError {
+    pub fn location(&self) -> Diagnostics {
+        let (file, line) = match self {
+            CompileError::InvalidInstruction { file, line }
+            | CompileError::ParamError { file, line }
+            | CompileError::LabelError { file, line }
+            | CompileError::ValueOutOfRange { file, line }
+            | CompileError::InvalidRegister { file, line }
+            | CompileError::EndNotReached { file, line }
+            | CompileError::InvalidDirective { file, line }
+            | CompileError::RecursiveInclude { file, line }
+            | CompileError::FileError { file, line, .. }
+            | CompileError::ReturnAddressLost { file, line } => (file, line),
+        };
+        Diagnostics { line: *line, file: file.clone() }
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::InvalidInstruction { .. } => write!(f, "Invalid instruction"),
+            CompileError::ParamError { .. } => write!(f, "Missing or invalid parameter"),
+            CompileError::LabelError { .. } => write!(f, "Unknown or duplicate label"),
+            CompileError::ValueOutOfRange { .. } => write!(f, "Value out of range for the register mode"),
+            CompileError::InvalidRegister { .. } => write!(f, "Invalid register"),
+            CompileError::EndNotReached { .. } => write!(f, "END is not reached on every path"),
+            CompileError::InvalidDirective { .. } => write!(f, "Malformed directive"),
+            CompileError::RecursiveInclude { .. } => write!(f, "File includes itself"),
+            CompileError::FileError { message, .. } => write!(f, "Cannot read file: {}", message),
+            CompileError::ReturnAddressLost { .. } => write!(f, "A running CALL returns to a place the changed program doesn't have"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize)]
@@ -73,82 +252,378 @@ pub enum ExecutionError {
     EndMarkerMissing,
     NotImplemented,
     DivThroughZero,
-    AccessingReg0
+    AccessingReg0,
+    InvalidPointer,
+    RegisterOutOfRange,
+    StepLimitExceeded {
+        steps: u64,
+    },
+    HistoryExhausted,
+    ReturnWithoutCall,
+    InvalidValue,
+    NoInstructionAtLine,
+    /// READ without values left on the input tape
+    InputExhausted,
+    /// Step out outside of a subroutine
+    NotInSubroutine,
+    /// Jumping to a step the program doesn't reach, it ends after `steps` steps
+    EndedBeforeStep {
+        steps: u64,
+    },
+    /// The machine reached the state after step `start` again and repeats every `length` steps
+    NonTermination {
+        start: u64,
+        length: u64,
+    },
+}
+
+impl std::fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::EndMarkerMissing => write!(f, "The program ran past its last instruction without reaching END"),
+            ExecutionError::NotImplemented => write!(f, "This instruction is not implemented"),
+            ExecutionError::DivThroughZero => write!(f, "Division by zero"),
+            ExecutionError::AccessingReg0 => write!(f, "Register 0 doesn't exist, registers start at 1"),
+            ExecutionError::InvalidPointer => write!(f, "The pointer doesn't refer to a valid register"),
+            ExecutionError::RegisterOutOfRange => write!(f, "The register is beyond the highest allowed register"),
+            ExecutionError::StepLimitExceeded { steps } => write!(f, "Stopped after {} steps without reaching END", steps),
+            ExecutionError::HistoryExhausted => write!(f, "No earlier steps are recorded"),
+            ExecutionError::ReturnWithoutCall => write!(f, "RET without a matching CALL"),
+            ExecutionError::InvalidValue => write!(f, "The value doesn't fit the register mode"),
+            ExecutionError::NoInstructionAtLine => write!(f, "There is no instruction on this line"),
+            ExecutionError::InputExhausted => write!(f, "READ found no more values on the input tape"),
+            ExecutionError::NotInSubroutine => write!(f, "Not inside a subroutine, there is nothing to step out of"),
+            ExecutionError::EndedBeforeStep { steps } => write!(f, "The program ends after {} steps", steps),
+            ExecutionError::NonTermination { start, length } => {
+                write!(f, "The program never ends: the state after step {} repeats every {} steps", start, length)
+            },
+        }
+    }
+}
+
+/// Where a runtime error happened and the state at that point
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ErrorContext {
+    pub line: Diagnostics,
+    pub instruction: String,
+    /// Number of the step that failed, counting from 1
+    pub step: u64,
+    pub accumulator: String,
+    /// Registers the failing instruction accessed before the error
+    pub registers: BTreeMap<usize, String>,
+}
+
+/// Why the register mode or the highest register couldn't be changed
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum ReconfigureError {
+    /// A register, the accumulator or the input tape holds a value the new mode can't represent
+    InvalidValue,
+    /// A register beyond the new highest register is in use
+    RegisterOutOfRange,
+    /// The loaded program doesn't compile with the new setting
+    Compile(CompileError),
+}
+
+impl std::fmt::Display for ReconfigureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReconfigureError::InvalidValue => write!(f, "The machine holds values the new register mode can't represent"),
+            ReconfigureError::RegisterOutOfRange => write!(f, "Registers beyond the new highest register are in use"),
+            ReconfigureError::Compile(error) => write!(f, "The program doesn't compile with the new setting: {}", error),
+        }
+    }
+}
+
+/// An `ExecutionError` with a human readable message and, if it happened while
+/// executing, the failing instruction
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RuntimeError {
+    pub error: ExecutionError,
+    pub message: String,
+    pub context: Option<Box<ErrorContext>>,
+}
+
+impl From<ExecutionError> for RuntimeError {
+    fn from(error: ExecutionError) -> Self {
+        RuntimeError {
+            message: error.to_string(),
+            error,
+            context: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub enum ExecutionResult {
     End{
         line: Diagnostics,
-        register: Vec<u32>,
-        accumulator: u32,
+        changed: BTreeMap<usize, String>,
+        accumulator: String,
+        next: u32,
+        cost: Cost,
     },
     Executed {
         line: Diagnostics,
-        register: Vec<u32>,
-        accumulator: u32,
+        /// Registers written by this step
+        changed: BTreeMap<usize, String>,
+        accumulator: String,
+        /// Index into `lines` of the next instruction
+        next: u32,
+        /// Cost of all steps executed so far, including this one
+        cost: Cost,
+        /// Value taken from the input tape by this step
+        read: Option<String>,
+        /// Value appended to the output tape by this step
+        written: Option<String>,
     },
 }
 
+/// Full machine state, step results only carry what changed
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Snapshot {
+    pub register: BTreeMap<usize, String>,
+    pub accumulator: String,
+    pub line_ptr: u32,
+    pub call_stack: Vec<u32>,
+    /// Number of steps executed so far
+    pub step: u64,
+    pub cost: Cost,
+    /// Values of the input tape taken so far
+    pub input_position: usize,
+    pub output: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RunResult {
+    pub result: ExecutionResult,
+    pub steps: u64,
+    pub breakpoint: Option<BreakpointHit>,
+}
+
+/// Where execution continues after recompiling a running program
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub enum PcMapping {
+    /// Found at the same distance from the nearest preceding label
+    Label { line: Diagnostics },
+    /// Found at the same source line
+    Line { line: Diagnostics },
+    /// Label and source line point to different instructions, the label position is used
+    Ambiguous { chosen: Diagnostics, alternative: Diagnostics },
+    /// No equivalent position exists, execution restarts at the beginning
+    NotFound,
+    /// The program had already ended
+    Ended,
+}
+
 macro_rules! executed {
-    ($file:expr, $line:expr, $register:expr, $accumulator:expr) => {
+    ($file:expr, $line:expr, $changed:expr, $accumulator:expr, $next:expr, $cost:expr) => {
+        executed!($file, $line, $changed, $accumulator, $next, $cost, None, None)
+    };
+    ($file:expr, $line:expr, $changed:expr, $accumulator:expr, $next:expr, $cost:expr, $read:expr, $written:expr) => {
         ExecutionResult::Executed {
             line: Diagnostics {
                 line: $line,
                 file: $file,
             },
-            register: $register,
-            accumulator: $accumulator,
+            changed: $changed,
+            accumulator: $accumulator.to_string(),
+            next: $next,
+            cost: $cost,
+            read: $read,
+            written: $written,
         }
     };
 }
 
-impl VirtualMachine {
-    pub fn new() -> VirtualMachine {
+impl<M: Registers> VirtualMachine<M> {
+    pub fn new() -> VirtualMachine<M> {
         VirtualMachine {
-            memory: vec![0; 0],
-            accumulator: 0,
+            memory: M::default(),
+            accumulator: BigInt::zero(),
+            register_mode: RegisterMode::Standard,
+            max_register: None,
             lines: Vec::new(),
             line_ptr: 0,
+            call_stack: Vec::new(),
             defines: HashMap::new(),
-            labels: Vec::new(),
+            labels: HashMap::new(),
+            breakpoints: Breakpoints::default(),
+            accesses: Vec::new(),
+            history: History::default(),
+            trace: Trace::default(),
+            cost: Cost::default(),
+            cost_model: CostModel::default(),
+            profiler: Profiler::default(),
+            coverage: Coverage::default(),
+            cycles: CycleDetector::default(),
+            tapes: Tapes::default(),
+            end_check: EndCheck::Warning,
+            warnings: Vec::new(),
+            program: None,
+            sources: BTreeMap::new(),
+            stopped_at: None,
         }
     }
 
     pub fn reuse(&mut self) {
-        self.memory = vec![0; 0];
-        self.accumulator = 0;
+        self.memory.clear();
+        self.accumulator = BigInt::zero();
         self.lines = Vec::new();
         self.line_ptr = 0;
+        self.call_stack = Vec::new();
         self.defines = HashMap::new();
-        self.labels = Vec::new();
+        self.labels = HashMap::new();
+        self.warnings = Vec::new();
+        self.program = None;
+        self.sources = BTreeMap::new();
+        self.breakpoints.reset_hits();
+        self.accesses = Vec::new();
+        self.history.clear();
+        self.trace.clear();
+        self.cost = Cost::default();
+        self.profiler.clear();
+        self.cycles.clear();
+        self.tapes.rewind();
+        self.stopped_at = None;
+    }
+
+    /// Brings a computed value back into the range of the current register mode
+    pub fn normalize(&self, value: BigInt) -> BigInt {
+        match self.register_mode {
+            RegisterMode::Standard => value & ((BigInt::one() << WordWidth::W32.bits()) - 1),
+            RegisterMode::Unsigned(width) => value & ((BigInt::one() << width.bits()) - 1),
+            RegisterMode::Signed(width) => {
+                let modulus = BigInt::one() << width.bits();
+                let wrapped = value & (&modulus - 1);
+                if wrapped >= (&modulus >> 1) {
+                    wrapped - modulus
+                } else {
+                    wrapped
+                }
+            },
+            RegisterMode::Natural => if value.sign() == Sign::Minus {
+                BigInt::zero()
+            } else {
+                value
+            },
+            RegisterMode::Integer => value,
+        }
+    }
+
+    /// Checks whether a literal value can be represented without wrapping
+    pub fn fits(&self, value: &BigInt) -> bool {
+        match self.register_mode {
+            RegisterMode::Standard | RegisterMode::Natural | RegisterMode::Unsigned(_) | RegisterMode::Signed(_) => {
+                self.normalize(value.clone()) == *value
+            },
+            RegisterMode::Integer => true,
+        }
+    }
+
+    /// Parses a register value as entered by the user, empty fields count as zero
+    pub fn parse_value(&self, value: &str) -> Option<BigInt> {
+        if value.trim().is_empty() {
+            return Some(BigInt::zero());
+        }
+        value.trim().parse::<BigInt>().ok().filter(|v| self.fits(v))
+    }
+
+    fn check_register(&self, index: usize) -> Result<usize, ExecutionError> {
+        if index == 0 {
+            return Err(ExecutionError::AccessingReg0);
+        }
+        if let Some(max) = self.max_register {
+            if index > max {
+                return Err(ExecutionError::RegisterOutOfRange);
+            }
+        }
+        Ok(index)
+    }
+
+    /// The register a pointer refers to, negative and oversized pointers are invalid
+    fn register_index(&self, value: &BigInt) -> Result<usize, ExecutionError> {
+        let index = value.to_usize().ok_or(ExecutionError::InvalidPointer)?;
+        self.check_register(index)
+    }
+
+    fn read_register(&mut self, index: usize) -> BigInt {
+        self.history.record(index, self.memory.get(index));
+        self.accesses.push(RegisterAccess { register: index, kind: AccessKind::Read });
+        self.memory.read(index)
+    }
+
+    fn write_register(&mut self, index: usize, value: BigInt) {
+        self.history.record(index, self.memory.get(index));
+        self.accesses.push(RegisterAccess { register: index, kind: AccessKind::Write });
+        self.memory.write(index, value)
+    }
+
+    /// The line holding the next instruction, skipping labels
+    pub fn current_line(&self) -> Option<&Line> {
+        self.current_index().map(|index| &self.lines[index])
     }
 
-    fn resize_memory(&mut self, size: u32) {
-        if size as usize > self.memory.len() {
-            self.memory.resize(size as usize, 0);
+    /// Index into `lines` of the next instruction
+    fn current_index(&self) -> Option<usize> {
+        (self.line_ptr as usize..self.lines.len()).find(|index| self.lines[*index].instruction.is_some())
+    }
+
+    /// Whether the next instruction is a conditional jump that will be taken
+    fn branch_taken(&self) -> Option<bool> {
+        match self.current_line().and_then(|line| line.instruction.as_ref()) {
+            Some(Instruction::JumpIfZero(_)) => Some(self.accumulator.is_zero()),
+            Some(Instruction::JumpIfNotZero(_)) => Some(!self.accumulator.is_zero()),
+            _ => None,
+        }
+    }
+
+    /// Checks the line breakpoints of the next instruction. Stopping at the same
+    /// place twice in a row is suppressed, so execution can continue from a breakpoint.
+    pub fn check_line_breakpoints(&mut self) -> Option<BreakpointHit> {
+        if self.stopped_at == Some(self.line_ptr) {
+            return None;
         }
+
+        let (file, line) = match self.current_line() {
+            Some(line) => (line.file_name.clone(), line.line_number),
+            None => return None,
+        };
+        let hit = self.breakpoints.check_line(&file, line, &self.accumulator, &self.memory);
+        if hit.is_some() {
+            self.stopped_at = Some(self.line_ptr);
+        }
+        hit
+    }
+
+    /// Checks the watchpoints against the registers accessed by the last step
+    pub fn check_watchpoints(&mut self) -> Option<BreakpointHit> {
+        self.breakpoints.check_accesses(&self.accesses, &self.accumulator, &self.memory)
+    }
+
+    /// Parses a register number, rejecting register 0 and registers beyond `max_register`
+    fn compute_register(&self, arg: &str, line_nr: u32, file_name: &str) -> Result<RegisterIndex, CompileError> {
+        let number = arg.parse::<BigInt>()
+            .map_err(|_| CompileError::ParamError { file: file_name.to_owned(), line: line_nr })?;
+        number.to_usize()
+            .and_then(RegisterIndex::new)
+            .filter(|index| self.max_register.is_none_or(|max| index.get() <= max))
+            .ok_or(CompileError::InvalidRegister { file: file_name.to_owned(), line: line_nr })
     }
 
     fn compute_ptr_type(&self, arg: String, line_nr: u32, file_name: String) -> Result<PtrType, CompileError> {
         let mut mut_arg = arg.clone();
         return if arg.clone().starts_with("*") {
             mut_arg.remove(0);
-            match mut_arg.parse::<i32>() {
-                Ok(i) => Ok(PtrType::Pointer(i)),
-                Err(_) => Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() }),
-            }
+            Ok(PtrType::Pointer(self.compute_register(&mut_arg, line_nr, &file_name)?))
         } else if arg.starts_with("#") {
             mut_arg.remove(0);
-            match mut_arg.parse::<i32>() {
-                Ok(i) => Ok(PtrType::Immediate(i)),
+            match mut_arg.parse::<BigInt>() {
+                Ok(i) if self.fits(&i) => Ok(PtrType::Immediate(i)),
+                Ok(_) => Err(CompileError::ValueOutOfRange { file: file_name.clone(), line: line_nr.clone() }),
                 Err(_) => Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() }),
             }
         } else {
-            match mut_arg.parse::<i32>() {
-                Ok(i) => Ok(PtrType::Register(i)),
-                Err(_) => Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() }),
-            }
+            Ok(PtrType::Register(self.compute_register(&mut_arg, line_nr, &file_name)?))
         }
     }
 
@@ -156,26 +631,15 @@ impl VirtualMachine {
         let mut mut_arg = arg.clone();
         return if arg.clone().starts_with("*") {
             mut_arg.remove(0);
-            if let Ok(i) = mut_arg.parse::<i32>() {
-                Ok(RefPtrType::Pointer(i))
-            } else {
-                Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
-            }
+            Ok(RefPtrType::Pointer(self.compute_register(&mut_arg, line_nr, &file_name)?))
         } else {
-            match mut_arg.parse::<i32>() {
-                Ok(i) => Ok(RefPtrType::Register(i)),
-                Err(_) => Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() }),
-            }
+            Ok(RefPtrType::Register(self.compute_register(&mut_arg, line_nr, &file_name)?))
         }
     }
 
-    fn compute_label(&self, arg: String, line_nr: u32, file_name: String) -> Result<String, CompileError> {
-        // Check if label exists
-        return if self.labels.contains(&arg) {
-            Ok(arg)
-        } else {
-            Err(CompileError::LabelError { file: file_name.clone(), line: line_nr.clone() })
-        }
+    fn compute_label(&self, arg: String) -> Jump {
+        // The target is filled in by link() once all files are loaded
+        Jump { label: arg, target: 0 }
     }
 
     fn compile(
@@ -218,20 +682,38 @@ impl VirtualMachine {
                 Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
             },
             "jzero" => if let Some(arg) = arg {
-                Ok(Instruction::JumpIfZero(self.compute_label(arg, line_nr.clone(), file_name.clone())?))
+                Ok(Instruction::JumpIfZero(self.compute_label(arg)))
             } else {
                 Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
             },
             "jnzero" => if let Some(arg) = arg {
-                Ok(Instruction::JumpIfNotZero(self.compute_label(arg, line_nr.clone(), file_name.clone())?))
+                Ok(Instruction::JumpIfNotZero(self.compute_label(arg)))
             } else {
                 Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
             },
             "goto" => if let Some(arg) = arg {
-                Ok(Instruction::Goto(self.compute_label(arg, line_nr.clone(), file_name.clone())?))
+                Ok(Instruction::Goto(self.compute_label(arg)))
+            } else {
+                Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
+            },
+            "call" => if let Some(arg) = arg {
+                Ok(Instruction::Call(self.compute_label(arg)))
             } else {
                 Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
             },
+            "ret" => if let None = arg {
+                Ok(Instruction::Return())
+            } else {
+                Err(CompileError::ParamError { file: file_name.clone(), line: line_nr.clone() })
+            },
+            "read" => match arg {
+                Some(arg) => Ok(Instruction::Read(Some(self.compute_store_type(arg, line_nr, file_name.clone())?))),
+                None => Ok(Instruction::Read(None)),
+            },
+            "write" => match arg {
+                Some(arg) => Ok(Instruction::Write(Some(self.compute_ptr_type(arg, line_nr, file_name.clone())?))),
+                None => Ok(Instruction::Write(None)),
+            },
             "end" => if let None = arg {
                 Ok(Instruction::End())
             } else {
@@ -243,48 +725,227 @@ impl VirtualMachine {
     }
 
     pub fn load(&mut self, code: &PathBuf) -> Result<(), CompileError> {
-        let mut line_number = 0;
+        self.load_with(code, &read_source)
+    }
 
-        let file = std::fs::read_to_string(code).unwrap();
+    /// Compiles a program from `sources` instead of the files on disk, keyed like `self.sources`
+    pub fn load_sources(&mut self, code: &PathBuf, sources: &BTreeMap<String, String>) -> Result<(), CompileError> {
+        self.load_with(code, &|path: &PathBuf, file: &str, line: u32| {
+            sources.get(path.to_string_lossy().as_ref()).cloned().ok_or_else(|| CompileError::FileError {
+                file: file.to_owned(),
+                line,
+                message: format!("{} is not part of the program", path.to_string_lossy()),
+            })
+        })
+    }
 
-        // Load labels
-        for line in file.lines() {
-            let trimmed_line = line.trim();
-            let mut tokens = trimmed_line.split_whitespace().peekable();
-            // Process labels
+    fn load_with(&mut self, code: &PathBuf, read: &SourceReader) -> Result<(), CompileError> {
+        let source = read(code, &code.to_string_lossy(), 0)?;
+        self.sources.clear();
+        self.load_file(code, &source, read, &mut Vec::new())?;
+        self.link()?;
+        self.program = Some(code.clone());
 
-            if tokens.peek().is_none() {
-                continue;
+        self.warnings = match self.end_check {
+            EndCheck::Off => Vec::new(),
+            EndCheck::Warning | EndCheck::Error => check_end(&self.lines, &code.to_string_lossy()),
+        };
+        if self.end_check == EndCheck::Error {
+            if let Some(warning) = self.warnings.first() {
+                let (file, line) = match warning {
+                    CompileWarning::FallsOffEnd { file, line } => (file.clone(), *line),
+                    CompileWarning::EndUnreachable { file, line } => (file.clone(), *line),
+                };
+                return Err(CompileError::EndNotReached { file, line });
             }
+        }
 
-            if tokens.peek().unwrap().ends_with(":") {
-                let label = tokens.next().unwrap();
-                self.labels.push(label[..label.len() - 1].to_owned());
+        self.coverage.register(&self.lines);
+        Ok(())
+    }
+
+    /// Compiles the program again while keeping registers and accumulator. The program
+    /// counter and call stack are mapped to the equivalent positions in the new program.
+    /// On a compile error, or if a return address can't be mapped, the old program stays loaded.
+    pub fn recompile(&mut self, code: &PathBuf) -> Result<PcMapping, CompileError> {
+        let old_lines = std::mem::take(&mut self.lines);
+        let old_defines = std::mem::take(&mut self.defines);
+        let old_labels = std::mem::take(&mut self.labels);
+        let old_warnings = std::mem::take(&mut self.warnings);
+        let old_program = self.program.take();
+        let old_sources = std::mem::take(&mut self.sources);
+        let old_coverage = self.coverage.clone();
+
+        match self.load(code).and_then(|_| self.map_call_stack(&old_lines)) {
+            Ok(call_stack) => self.call_stack = call_stack,
+            Err(error) => {
+                self.lines = old_lines;
+                self.defines = old_defines;
+                self.labels = old_labels;
+                self.warnings = old_warnings;
+                self.program = old_program;
+                self.sources = old_sources;
+                self.coverage = old_coverage;
+                return Err(error);
             }
+        }
 
-            line_number += 1;
+        // Undo entries, trace and profile refer to the old program
+        self.history.drop_entries();
+        self.trace.clear();
+        self.profiler.clear();
+        self.cycles.clear();
+        self.stopped_at = None;
+
+        let (by_label, by_line) = self.map_position(&old_lines, self.line_ptr);
+        let mapping = match (by_label, by_line) {
+            _ if next_instruction(&old_lines, self.line_ptr).is_none() => {
+                self.line_ptr = self.lines.len() as u32;
+                PcMapping::Ended
+            }
+            (Some(label), Some(line)) if label != line => {
+                self.line_ptr = label;
+                PcMapping::Ambiguous { chosen: self.diagnostics(label), alternative: self.diagnostics(line) }
+            }
+            (Some(label), _) => {
+                self.line_ptr = label;
+                PcMapping::Label { line: self.diagnostics(label) }
+            }
+            (None, Some(line)) => {
+                self.line_ptr = line;
+                PcMapping::Line { line: self.diagnostics(line) }
+            }
+            (None, None) => {
+                self.line_ptr = 0;
+                PcMapping::NotFound
+            }
+        };
+
+        Ok(mapping)
+    }
+
+    /// Return addresses of the running CALLs in the loaded program
+    fn map_call_stack(&self, old_lines: &[Line]) -> Result<Vec<u32>, CompileError> {
+        self.call_stack.iter().map(|return_ptr| {
+            let old_index = match next_instruction(old_lines, *return_ptr) {
+                Some(index) => index,
+                // Returns behind the last instruction, as before
+                None => return Ok(self.lines.len() as u32),
+            };
+            let (by_label, by_line) = self.map_position(old_lines, *return_ptr);
+            by_label.or(by_line).ok_or_else(|| CompileError::ReturnAddressLost {
+                file: old_lines[old_index].file_name.clone(),
+                line: old_lines[old_index].line_number,
+            })
+        }).collect()
+    }
+
+    fn diagnostics(&self, index: u32) -> Diagnostics {
+        let line = &self.lines[index as usize];
+        Diagnostics {
+            line: line.line_number,
+            file: line.file_name.clone(),
+        }
+    }
+
+    /// Finds the position in the loaded program equivalent to `line_ptr` in `old_lines`,
+    /// once relative to the nearest preceding label and once by source line
+    fn map_position(&self, old_lines: &[Line], line_ptr: u32) -> (Option<u32>, Option<u32>) {
+        let current = match next_instruction(old_lines, line_ptr) {
+            Some(index) => index,
+            None => return (None, None),
+        };
+        let old_line = &old_lines[current];
+
+        // Count the instructions between the nearest label and the current one
+        let anchor = old_lines[..=current].iter().rposition(|line| line.label.is_some());
+        let (start, offset) = match anchor {
+            Some(anchor) => {
+                let label = old_lines[anchor].label.as_ref().unwrap();
+                let offset = old_lines[anchor..current].iter().filter(|line| line.instruction.is_some()).count();
+                (self.labels.get(label).map(|index| *index as usize), offset)
+            }
+            None => (Some(0), old_lines[..current].iter().filter(|line| line.instruction.is_some()).count()),
+        };
+        let by_label = start.and_then(|start| {
+            self.lines.iter().enumerate().skip(start)
+                .filter(|(_, line)| line.instruction.is_some())
+                .nth(offset)
+                .map(|(index, _)| index as u32)
+        });
+
+        let by_line = self.lines.iter()
+            .position(|line| {
+                line.instruction.is_some()
+                    && line.line_number >= old_line.line_number
+                    && same_file(&line.file_name, &old_line.file_name)
+            })
+            .map(|index| index as u32);
+
+        (by_label, by_line)
+    }
+
+    /// Builds the jump table and resolves all jump targets
+    fn link(&mut self) -> Result<(), CompileError> {
+        self.labels = HashMap::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            if let Some(label) = &line.label {
+                if self.labels.insert(label.clone(), index as u32).is_some() {
+                    return Err(CompileError::LabelError { file: line.file_name.clone(), line: line.line_number });
+                }
+            }
         }
 
-        line_number = 0;
+        for line in self.lines.iter_mut() {
+            let jump = match &mut line.instruction {
+                Some(Instruction::Goto(jump)) => jump,
+                Some(Instruction::JumpIfZero(jump)) => jump,
+                Some(Instruction::JumpIfNotZero(jump)) => jump,
+                Some(Instruction::Call(jump)) => jump,
+                _ => continue,
+            };
+
+            match self.labels.get(&jump.label) {
+                Some(target) => jump.target = *target,
+                None => return Err(CompileError::LabelError { file: line.file_name.clone(), line: line.line_number }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles `source`, the content of `code`. `includes` holds the files currently
+    /// being included, to reject recursive includes.
+    fn load_file(&mut self, code: &PathBuf, source: &str, read: &SourceReader, includes: &mut Vec<PathBuf>) -> Result<(), CompileError> {
+        let mut line_number = 0;
+        let file_name = code.to_string_lossy().into_owned();
+        includes.push(std::fs::canonicalize(code).unwrap_or_else(|_| code.clone()));
+        self.sources.insert(file_name.clone(), source.to_owned());
 
-        for line in file.lines() {
+        for line in source.lines() {
             // Process compiler directives
             let trimmed_line = line.trim();
+            let invalid_directive = || CompileError::InvalidDirective { file: file_name.clone(), line: line_number };
 
             if trimmed_line.starts_with("#define") {
                 let mut tokens = trimmed_line.split_whitespace();
                 tokens.next();
-                let name = tokens.next().unwrap();
-                let value = tokens.next().unwrap();
+                let name = tokens.next().ok_or_else(invalid_directive)?;
+                let value = tokens.next().ok_or_else(invalid_directive)?;
                 self.defines.insert(name.to_owned(), value.to_owned());
             } else if trimmed_line.starts_with("#include") {
                 let mut tokens = trimmed_line.split_whitespace();
                 tokens.next();
-                let name = tokens.next().unwrap();
+                let name = tokens.next().ok_or_else(invalid_directive)?;
                 let mut path = code.clone();
                 path.pop();
                 path.push(name);
-                self.load(&path)?;
+                let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if includes.contains(&canonical) {
+                    return Err(CompileError::RecursiveInclude { file: file_name, line: line_number });
+                }
+                let included = read(&path, &file_name, line_number)?;
+                self.load_file(&path, &included, read, includes)?;
             } else if !trimmed_line.is_empty() {
                 // Code
                 let mut tokens = trimmed_line.split_whitespace();
@@ -325,13 +986,13 @@ impl VirtualMachine {
                         token.to_owned(),
                         argument,
                         line_number.clone(),
-                        code.to_str().unwrap().to_owned(),
+                        file_name.clone(),
                     )?;
 
                     self.lines.push(Line {
                         line,
                         line_number,
-                        file_name: code.to_str().unwrap().to_owned(),
+                        file_name: file_name.clone(),
                         instruction: Some(instruction),
                         label: label_in_line,
                     });
@@ -339,7 +1000,7 @@ impl VirtualMachine {
                     self.lines.push(Line {
                         line: label.clone(),
                         line_number,
-                        file_name: code.to_str().unwrap().to_owned(),
+                        file_name: file_name.clone(),
                         instruction: None,
                         label: Some(label),
                     });
@@ -349,175 +1010,745 @@ impl VirtualMachine {
             line_number += 1;
         }
 
+        includes.pop();
         Ok(())
     }
-    fn resolve_label(&mut self, my_label: String) -> Result<u32, ExecutionError> {
-        let mut line_number = 0;
-        for line in self.lines.iter() {
-            if let Some(label) = &line.label {
-                if label == &my_label {
-                    break;
-                }
+    pub fn step(&mut self) -> Result<ExecutionResult, RuntimeError> {
+        self.accesses.clear();
+        self.stopped_at = None;
+        let state = match self.check_cycle() {
+            Ok(state) => state,
+            Err(error) => return Err(self.runtime_error(error)),
+        };
+        let step = self.history.step;
+        self.history.begin(self.line_ptr, &self.accumulator, self.cost);
+        let previous_cost = self.cost;
+        let cost = match self.current_line().and_then(|line| line.instruction.as_ref()) {
+            // END doesn't count as a step
+            Some(Instruction::End()) | None => Cost::default(),
+            Some(instruction) => self.cost_model.cost(instruction, &self.accumulator, &self.memory, &self.tapes),
+        };
+        self.cost.add(cost);
+        let index = self.current_index();
+        let branch = self.branch_taken();
+        let before = if self.trace.enabled {
+            if self.trace.entries.is_empty() {
+                self.trace.start(self.snapshot().register, self.accumulator.to_string());
             }
+            Some((self.current_line().cloned(), self.accumulator.to_string()))
+        } else {
+            None
+        };
 
-            line_number += 1;
+        let result = self.execute();
+        match &result {
+            Ok(ExecutionResult::Executed { .. }) => {
+                self.history.commit(&self.accesses);
+                // Only steps that ran count, a failed step leaves the state unchanged
+                if let Some(state) = state {
+                    self.cycles.record(state, step);
+                }
+                if let Some(index) = index {
+                    self.profiler.record(index, cost, branch);
+                    let line = &self.lines[index];
+                    self.coverage.record(&line.file_name, line.line_number, branch);
+                }
+            }
+            // END isn't a step, but reaching it still covers the line
+            Ok(ExecutionResult::End { .. }) => {
+                self.history.discard();
+                if let Some(index) = index {
+                    let line = &self.lines[index];
+                    self.coverage.record(&line.file_name, line.line_number, None);
+                }
+            }
+            // Failing doesn't change the machine
+            _ => {
+                self.history.discard();
+                self.cost = previous_cost;
+            }
         }
 
-        return Ok(line_number);
+        if let (Some((Some(line), accumulator_before)), Ok(_)) = (before, &result) {
+            self.record_trace(line, accumulator_before, &result);
+        }
+        result.map_err(|error| self.runtime_error(error))
     }
 
-    pub fn step(&mut self) -> Result<ExecutionResult, ExecutionError> {
-        // Check for a pointer overrun
-        if self.line_ptr >= self.lines.len() as u32 {
-            return Ok(ExecutionResult::End {
+    /// Attaches the current instruction and state to an error of the step about to run. After
+    /// running past the last instruction, that instruction is the one reported.
+    fn runtime_error(&self, error: ExecutionError) -> RuntimeError {
+        let last_line = || {
+            let end = (self.line_ptr as usize).min(self.lines.len());
+            self.lines[..end].iter().rev().find(|line| line.instruction.is_some())
+        };
+        let context = self.current_line().or_else(last_line).map(|line| {
+            let registers = self.accesses.iter()
+                .map(|access| (access.register, self.memory.peek(access.register).to_string()))
+                .collect();
+            Box::new(ErrorContext {
                 line: Diagnostics {
-                    line: self.lines[self.line_ptr as usize - 1].line_number.clone(),
-                    file: self.lines[self.line_ptr as usize - 1].file_name.clone(),
+                    line: line.line_number,
+                    file: line.file_name.clone(),
                 },
-                register: self.memory.clone(),
-                accumulator: self.accumulator,
-            });
+                instruction: line.line.clone(),
+                step: self.history.step + 1,
+                accumulator: self.accumulator.to_string(),
+                registers,
+            })
+        });
+        let message = match &context {
+            Some(context) => format!("{} in {}:{} ({})", error, context.line.file, context.line.line + 1, context.instruction),
+            None => error.to_string(),
+        };
+
+        RuntimeError { error, message, context }
+    }
+
+    /// Hash of the state the next step starts from, or an error if it was seen before.
+    /// `None` if loop detection is off or the next instruction is END.
+    fn check_cycle(&self) -> Result<Option<u64>, ExecutionError> {
+        if !self.cycles.enabled {
+            return Ok(None);
+        }
+        // END leaves the state unchanged, stepping on it again isn't a loop
+        let line_ptr = match self.current_index() {
+            Some(index) if !matches!(self.lines[index].instruction, Some(Instruction::End())) => index as u32,
+            _ => return Ok(None),
+        };
+        let state = hash_state(line_ptr, &self.accumulator, &self.memory, &self.call_stack, self.tapes.position);
+        match self.cycles.check(state) {
+            Some(start) => Err(ExecutionError::NonTermination { start, length: self.history.step - start }),
+            None => Ok(Some(state)),
         }
+    }
 
-        // Skip empty lines & labels
-        while self.lines[self.line_ptr as usize].instruction.is_none() {
-            self.line_ptr += 1;
+    fn record_trace(&mut self, line: Line, accumulator_before: String, result: &Result<ExecutionResult, ExecutionError>) {
+        // END doesn't count as a step, but still gets a row once
+        let step = match result {
+            Ok(ExecutionResult::End { .. }) => self.history.step + 1,
+            _ => self.history.step,
+        };
+        if self.trace.entries.last().is_some_and(|entry| entry.step == step) {
+            return;
         }
 
-        let line = &mut self.lines.clone()[self.line_ptr as usize];
-        let diagnostic_line = line.clone();
-        let instruction = line.instruction.as_ref().unwrap();
+        let registers = traced_registers(&self.accesses, |register| self.memory.peek(register).to_string());
+        self.trace.record(TraceEntry {
+            step,
+            line: Diagnostics {
+                line: line.line_number,
+                file: line.file_name,
+            },
+            instruction: line.line,
+            accumulator_before,
+            accumulator_after: self.accumulator.to_string(),
+            registers,
+        });
+    }
 
+    fn execute(&mut self) -> Result<ExecutionResult, ExecutionError> {
+        // Skip empty lines & labels, running past the last instruction means END is missing
+        self.line_ptr = match self.current_index() {
+            Some(index) => index as u32,
+            None => return Err(ExecutionError::EndMarkerMissing),
+        };
+
+        let line = &self.lines[self.line_ptr as usize];
+        let file_name = line.file_name.clone();
+        let line_number = line.line_number;
+        let instruction = line.instruction.clone().unwrap();
 
-        return match instruction {
+        return match &instruction {
             Instruction::Add(ptr) => {
-                let value: u32 = self.resolve_ptr(ptr)?;
+                let value = self.resolve_ptr(ptr)?;
 
-                self.accumulator = self.accumulator + value;
+                self.accumulator = self.normalize(&self.accumulator + value);
                 self.line_ptr += 1;
-                Ok(executed!(diagnostic_line.file_name.clone(), diagnostic_line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
             Instruction::Sub(ptr) => {
-                let value: u32 = self.resolve_ptr(ptr)?;
+                let value = self.resolve_ptr(ptr)?;
 
-                if value <= self.accumulator {
-                    self.accumulator = self.accumulator - value;
+                if self.register_mode != RegisterMode::Standard || value <= self.accumulator {
+                    self.accumulator = self.normalize(&self.accumulator - value);
                 }
                 self.line_ptr += 1;
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
             Instruction::Mul(ptr) => {
-                let value: u32 = self.resolve_ptr(ptr)?;
+                let value = self.resolve_ptr(ptr)?;
 
-                self.accumulator = self.accumulator * value;
+                self.accumulator = self.normalize(&self.accumulator * value);
                 self.line_ptr += 1;
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
             Instruction::Div(ptr) => {
-                let value: u32 = self.resolve_ptr(ptr)?;
-                if value == 0 {
+                let value = self.resolve_ptr(ptr)?;
+                if value.is_zero() {
                     return Err(ExecutionError::DivThroughZero);
                 }
 
-                self.accumulator = self.accumulator / value;
+                self.accumulator = self.normalize(&self.accumulator / value);
                 self.line_ptr += 1;
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
-            Instruction::Goto(label) => {
-                let label_line_number = self.resolve_label(label.clone())?;
-                self.line_ptr = label_line_number;
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+            Instruction::Goto(jump) => {
+                self.line_ptr = jump.target;
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
-            Instruction::JumpIfZero(label) => {
-                if self.accumulator == 0 {
-                    let label_line_number = self.resolve_label(label.clone())?;
-                    self.line_ptr = label_line_number;
+            Instruction::JumpIfZero(jump) => {
+                if self.accumulator.is_zero() {
+                    self.line_ptr = jump.target;
                 } else {
                     self.line_ptr += 1;
                 }
 
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
-            Instruction::JumpIfNotZero(label) => {
-                if self.accumulator != 0 {
-                    let label_line_number = self.resolve_label(label.clone())?;
-                    self.line_ptr = label_line_number;
+            Instruction::JumpIfNotZero(jump) => {
+                if !self.accumulator.is_zero() {
+                    self.line_ptr = jump.target;
                 } else {
                     self.line_ptr += 1;
                 }
 
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
             Instruction::Load(ptr) => {
                 let value = self.resolve_ptr(ptr);
                 self.accumulator = value?;
                 self.line_ptr += 1;
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
             }
 
             Instruction::Store(ptr) => {
-                let value = self.accumulator;
-                match ptr {
-                    RefPtrType::Register(i) => {
-                        self.resize_memory(i.clone() as u32 + 1);
-                        self.memory[i.clone() as usize-1] = value
+                let value = self.accumulator.clone();
+                let index = self.resolve_store(ptr)?;
+                self.write_register(index, value.clone());
+
+                self.line_ptr += 1;
+                let changed = BTreeMap::from([(index, value.to_string())]);
+                Ok(executed!(file_name, line_number, changed, &self.accumulator, self.line_ptr, self.cost))
+            }
+
+            Instruction::Call(jump) => {
+                self.history.record_call_stack(&self.call_stack);
+                self.call_stack.push(self.line_ptr + 1);
+                self.line_ptr = jump.target;
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
+            }
+
+            Instruction::Return() => {
+                self.history.record_call_stack(&self.call_stack);
+                self.line_ptr = self.call_stack.pop().ok_or(ExecutionError::ReturnWithoutCall)?;
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost))
+            }
+
+            Instruction::Read(ptr) => {
+                let index = match ptr {
+                    Some(ptr) => Some(self.resolve_store(ptr)?),
+                    None => None,
+                };
+                let value = self.tapes.peek().cloned().ok_or(ExecutionError::InputExhausted)?;
+                if !self.fits(&value) {
+                    return Err(ExecutionError::InvalidValue);
+                }
+                self.history.record_tapes(self.tapes.position, self.tapes.output.len());
+                self.tapes.read();
+
+                let changed = match index {
+                    Some(index) => {
+                        self.write_register(index, value.clone());
+                        BTreeMap::from([(index, value.to_string())])
                     },
-                    RefPtrType::Pointer(i) => {
-                        self.resize_memory(i.clone() as u32 + 1);
-                        let pos = self.memory[i.clone() as usize-1] as usize;
-                        self.resize_memory(pos as u32 + 1);
-                        self.memory[pos-1] = value
+                    None => {
+                        self.accumulator = value.clone();
+                        BTreeMap::new()
                     },
-                }
+                };
+                self.line_ptr += 1;
+                Ok(executed!(file_name, line_number, changed, &self.accumulator, self.line_ptr, self.cost, Some(value.to_string()), None))
+            }
+
+            Instruction::Write(ptr) => {
+                let value = match ptr {
+                    Some(ptr) => self.resolve_ptr(ptr)?,
+                    None => self.accumulator.clone(),
+                };
+                self.history.record_tapes(self.tapes.position, self.tapes.output.len());
+                self.tapes.write(value.clone());
 
                 self.line_ptr += 1;
-                Ok(executed!(line.file_name.clone(), line.line_number.clone(), self.memory.clone(), self.accumulator))
+                Ok(executed!(file_name, line_number, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost, None, Some(value.to_string())))
             }
 
             Instruction::End() => {
                 Ok(ExecutionResult::End {
                     line: Diagnostics {
-                        line: line.line_number.clone(),
-                        file: line.file_name.clone(),
+                        line: line_number,
+                        file: file_name,
                     },
-                    register: self.memory.clone(),
-                    accumulator: self.accumulator,
+                    changed: BTreeMap::new(),
+                    accumulator: self.accumulator.to_string(),
+                    next: self.line_ptr,
+                    cost: self.cost,
                 })
             }
         };
     }
 
-    fn resolve_ptr(&mut self, ptr: &PtrType) -> Result<u32, ExecutionError> {
-        let value: u32 = match ptr {
-            PtrType::Immediate(i) => i.clone() as u32,
-            PtrType::Register(i) => {
-                self.resize_memory(i.clone() as u32 + 1);
-                if i.clone() == 0 {
-                    return Err(ExecutionError::AccessingReg0);
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            register: self.memory.touched().iter().map(|(i, v)| (*i, v.to_string())).collect(),
+            accumulator: self.accumulator.to_string(),
+            line_ptr: self.line_ptr,
+            call_stack: self.call_stack.clone(),
+            step: self.history.step,
+            cost: self.cost,
+            input_position: self.tapes.position,
+            output: self.tapes.output.iter().map(|value| value.to_string()).collect(),
+        }
+    }
+
+    /// Restarts the loaded program, keeping the compiled lines
+    pub fn reset(&mut self) {
+        self.memory.clear();
+        self.accumulator = BigInt::zero();
+        self.line_ptr = 0;
+        self.call_stack = Vec::new();
+        self.breakpoints.reset_hits();
+        self.accesses = Vec::new();
+        self.history.clear();
+        self.trace.clear();
+        self.cost = Cost::default();
+        self.profiler.clear();
+        self.cycles.clear();
+        self.tapes.rewind();
+        self.stopped_at = None;
+    }
+
+    pub fn set_register(&mut self, index: usize, value: BigInt) -> Result<(), ExecutionError> {
+        let index = self.check_register(index)?;
+        if !self.fits(&value) {
+            return Err(ExecutionError::InvalidValue);
+        }
+        self.memory.write(index, value);
+        self.cycles.clear();
+        Ok(())
+    }
+
+    pub fn set_accumulator(&mut self, value: BigInt) -> Result<(), ExecutionError> {
+        if !self.fits(&value) {
+            return Err(ExecutionError::InvalidValue);
+        }
+        self.accumulator = value;
+        self.cycles.clear();
+        Ok(())
+    }
+
+    /// Switches the register mode. Registers, accumulator and input tape have to fit the new
+    /// mode, and a loaded program is compiled again from its sources so its immediates are
+    /// checked as well. On error the machine is left unchanged.
+    pub fn set_register_mode(&mut self, mode: RegisterMode) -> Result<(), ReconfigureError> {
+        let mut changed = self.clone();
+        changed.register_mode = mode;
+        let values_fit = changed.fits(&changed.accumulator)
+            && changed.memory.touched().values().all(|value| changed.fits(value))
+            && changed.tapes.input.iter().all(|value| changed.fits(value));
+        if !values_fit {
+            return Err(ReconfigureError::InvalidValue);
+        }
+        changed.compile_again()?;
+
+        // Undo entries may hold values of the old mode
+        changed.history.drop_entries();
+        changed.cycles.clear();
+        *self = changed;
+        Ok(())
+    }
+
+    /// Limits the registers to `max`. No register beyond it may be in use, and a loaded
+    /// program is compiled again from its sources so its operands are checked as well.
+    /// On error the machine is left unchanged.
+    pub fn set_max_register(&mut self, max: Option<usize>) -> Result<(), ReconfigureError> {
+        let mut changed = self.clone();
+        changed.max_register = max;
+        if let Some(max) = max {
+            if changed.memory.touched().keys().any(|register| *register > max) {
+                return Err(ReconfigureError::RegisterOutOfRange);
+            }
+        }
+        changed.compile_again()?;
+        *self = changed;
+        Ok(())
+    }
+
+    /// Compiles the loaded program again from its sources, for settings checked at compile
+    /// time. The sources are the same, so the lines and with them the program counter stay.
+    fn compile_again(&mut self) -> Result<(), ReconfigureError> {
+        if let Some(program) = self.program.clone() {
+            let sources = std::mem::take(&mut self.sources);
+            self.lines = Vec::new();
+            self.defines = HashMap::new();
+            self.labels = HashMap::new();
+            self.load_sources(&program, &sources).map_err(ReconfigureError::Compile)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the program counter to the instruction on a source line, `line` is zero based
+    pub fn move_to_line(&mut self, file: &str, line: u32) -> Result<(), ExecutionError> {
+        let index = self.lines.iter()
+            .position(|l| l.line_number == line && l.instruction.is_some() && same_file(&l.file_name, file))
+            .ok_or(ExecutionError::NoInstructionAtLine)?;
+        self.line_ptr = index as u32;
+        self.stopped_at = None;
+        self.cycles.clear();
+        Ok(())
+    }
+
+    /// Undoes the last executed step
+    pub fn step_back(&mut self) -> Result<Snapshot, ExecutionError> {
+        let entry = self.history.pop().ok_or(ExecutionError::HistoryExhausted)?;
+        let cost = self.cost.since(entry.cost);
+        self.line_ptr = entry.line_ptr;
+        self.accumulator = entry.accumulator;
+        self.cost = entry.cost;
+        for (register, value) in entry.registers {
+            match value {
+                Some(value) => self.memory.write(register, value),
+                None => self.memory.remove(register),
+            }
+        }
+        if let Some(call_stack) = entry.call_stack {
+            self.call_stack = call_stack;
+        }
+        if let Some((position, output_length)) = entry.tapes {
+            self.tapes.restore(position, output_length);
+        }
+        self.accesses = entry.accesses;
+        self.stopped_at = None;
+        self.trace.truncate(self.history.step);
+        self.cycles.clear();
+        if let Some(index) = self.current_index() {
+            let branch = self.branch_taken();
+            self.profiler.unrecord(index, cost, branch);
+            let line = &self.lines[index];
+            self.coverage.unrecord(&line.file_name, line.line_number, branch);
+        }
+
+        Ok(self.snapshot())
+    }
+
+    /// Steps back until a breakpoint matches or the history is exhausted. Logpoints
+    /// and hit counts are ignored while going backwards.
+    pub fn run_back(&mut self) -> Result<(Snapshot, Option<BreakpointHit>), ExecutionError> {
+        self.step_back()?;
+        loop {
+            // The undone step accessed these registers, so a watchpoint stops here
+            let mut hit = self.breakpoints.find_accesses(&self.accesses, &self.accumulator, &self.memory);
+            if hit.is_none() {
+                if let Some(line) = self.current_line() {
+                    hit = self.breakpoints.find_line(&line.file_name, line.line_number, &self.accumulator, &self.memory);
                 }
-                self.memory[i.clone() as usize - 1]
+            }
+            if hit.is_some() {
+                // Continuing forward shouldn't stop here again
+                self.stopped_at = Some(self.line_ptr);
+                return Ok((self.snapshot(), hit));
+            }
+
+            if self.history.step == self.history.oldest_step() {
+                return Ok((self.snapshot(), None));
+            }
+            self.step_back()?;
+        }
+    }
+
+    /// Moves to the state after `step` executed steps, going backwards through the
+    /// history or forwards by executing. If the program ends earlier, it stays at END and
+    /// `EndedBeforeStep` is returned.
+    pub fn jump_to_step(&mut self, step: u64) -> Result<Snapshot, RuntimeError> {
+        if step < self.history.oldest_step() {
+            return Err(ExecutionError::HistoryExhausted.into());
+        }
+        while self.history.step > step {
+            self.step_back()?;
+        }
+        while self.history.step < step {
+            if let ExecutionResult::End { .. } = self.step()? {
+                let error = ExecutionError::EndedBeforeStep { steps: self.history.step };
+                return Err(self.runtime_error(error));
+            }
+        }
+
+        Ok(self.snapshot())
+    }
+
+    /// Runs until the program ends or `max_steps` instructions were executed
+    pub fn run(&mut self, max_steps: u64) -> Result<RunResult, RuntimeError> {
+        self.run_until(max_steps, |_, _| false)
+    }
+
+    /// Runs until the program ends, a breakpoint is hit, `condition` returns true
+    /// after a step or `max_steps` instructions were executed
+    pub fn run_until<F>(&mut self, max_steps: u64, mut condition: F) -> Result<RunResult, RuntimeError>
+    where
+        F: FnMut(&Self, &ExecutionResult) -> bool,
+    {
+        let mut steps = 0;
+        let mut last = None;
+        while steps < max_steps {
+            if let Some(hit) = self.check_line_breakpoints() {
+                // Stopping before anything ran reports the current state
+                let result = last.unwrap_or_else(|| self.current_result());
+                return Ok(RunResult { result, steps, breakpoint: Some(hit) });
+            }
+
+            let result = self.step()?;
+            steps += 1;
+
+            if let ExecutionResult::End { .. } = result {
+                return Ok(RunResult { result, steps, breakpoint: None });
+            }
+            if let Some(hit) = self.check_watchpoints() {
+                return Ok(RunResult { result, steps, breakpoint: Some(hit) });
+            }
+            if condition(self, &result) {
+                return Ok(RunResult { result, steps, breakpoint: None });
+            }
+            last = Some(result);
+        }
+
+        Err(self.runtime_error(ExecutionError::StepLimitExceeded { steps }))
+    }
+
+    /// Executes the next instruction, a CALL runs until the subroutine returned
+    pub fn step_over(&mut self, max_steps: u64) -> Result<RunResult, RuntimeError> {
+        self.stopped_at = Some(self.line_ptr);
+        let depth = self.call_stack.len();
+        let is_call = matches!(self.current_line().and_then(|line| line.instruction.as_ref()), Some(Instruction::Call(_)));
+        if is_call {
+            self.run_until(max_steps, |vm, _| vm.call_stack.len() <= depth)
+        } else {
+            self.run_until(max_steps, |_, _| true)
+        }
+    }
+
+    /// Runs until the current subroutine returned
+    pub fn step_out(&mut self, max_steps: u64) -> Result<RunResult, RuntimeError> {
+        let depth = self.call_stack.len();
+        if depth == 0 {
+            return Err(self.runtime_error(ExecutionError::NotInSubroutine));
+        }
+        self.stopped_at = Some(self.line_ptr);
+        self.run_until(max_steps, |vm, _| vm.call_stack.len() < depth)
+    }
+
+    /// Runs until the instruction on a line is next, using a temporary breakpoint
+    pub fn run_to_line(&mut self, file: String, line: u32, max_steps: u64) -> Result<RunResult, RuntimeError> {
+        self.stopped_at = Some(self.line_ptr);
+        let id = self.breakpoints.add(BreakpointKind::Line { file, line }, None, None, None);
+        let result = self.run(max_steps);
+        self.breakpoints.remove(id);
+        result
+    }
+
+    /// Describes the current position as if the previous instruction had just run
+    fn current_result(&self) -> ExecutionResult {
+        let (file, line) = match self.current_line() {
+            Some(line) => (line.file_name.clone(), line.line_number),
+            None => (String::new(), 0),
+        };
+        executed!(file, line, BTreeMap::new(), &self.accumulator, self.line_ptr, self.cost)
+    }
+
+    /// Register written by STORE or READ
+    fn resolve_store(&mut self, ptr: &RefPtrType) -> Result<usize, ExecutionError> {
+        match ptr {
+            RefPtrType::Register(i) => self.check_register(i.get()),
+            RefPtrType::Pointer(i) => {
+                let index = self.check_register(i.get())?;
+                let pointer = self.read_register(index);
+                self.register_index(&pointer)
+            },
+        }
+    }
+
+    fn resolve_ptr(&mut self, ptr: &PtrType) -> Result<BigInt, ExecutionError> {
+        let value = match ptr {
+            PtrType::Immediate(i) => self.normalize(i.clone()),
+            PtrType::Register(i) => {
+                let index = self.check_register(i.get())?;
+                self.read_register(index)
             },
             PtrType::Pointer(i) => {
-                self.resize_memory(i.clone() as u32 + 1);
-                if i.clone() == 0 {
-                    return Err(ExecutionError::AccessingReg0);
-                }
-                let pos = self.memory[i.clone() as usize - 1] as usize;
-                if pos == 0 {
-                    return Err(ExecutionError::AccessingReg0);
-                }
-                self.resize_memory(pos as u32 + 1);
-                println!("Resolved pointer {} to {}", i.clone(), pos - 1);
-                self.memory[pos - 1]
+                let index = self.check_register(i.get())?;
+                let pointer = self.read_register(index);
+                let pos = self.register_index(&pointer)?;
+                self.read_register(pos)
             },
         };
         Ok(value)
     }
 }
+
+/// Reads the source of a file, errors are reported at `file` and `line`
+type SourceReader<'a> = dyn Fn(&PathBuf, &str, u32) -> Result<String, CompileError> + 'a;
+
+/// Reads a source file from disk, errors are reported at `file` and `line`
+fn read_source(path: &PathBuf, file: &str, line: u32) -> Result<String, CompileError> {
+    std::fs::read_to_string(path).map_err(|error| CompileError::FileError {
+        file: file.to_owned(),
+        line,
+        message: error.to_string(),
+    })
+}
+
+/// Index of the first instruction at or after `line_ptr`, skipping labels
+fn next_instruction(lines: &[Line], line_ptr: u32) -> Option<usize> {
+    (line_ptr as usize..lines.len()).find(|index| lines[*index].instruction.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(source: &str) -> VirtualMachine {
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        vm.end_check = EndCheck::Off;
+        let sources = BTreeMap::from([("test.rm".to_owned(), source.to_owned())]);
+        vm.load_sources(&PathBuf::from("test.rm"), &sources).unwrap();
+        vm
+    }
+
+    #[test]
+    fn running_past_the_end_reports_the_last_instruction() {
+        let mut vm = compile("LOAD #1\nADD #2\n\n");
+        let error = vm.run(100).unwrap_err();
+        assert_eq!(error.error, ExecutionError::EndMarkerMissing);
+        let context = error.context.unwrap();
+        assert_eq!(context.line, Diagnostics { line: 1, file: "test.rm".to_owned() });
+        assert_eq!(context.step, 3);
+        assert_eq!(context.accumulator, "3");
+    }
+
+    #[test]
+    fn empty_program_has_no_error_location() {
+        let mut vm = compile("\n");
+        let error = vm.step().unwrap_err();
+        assert_eq!(error.error, ExecutionError::EndMarkerMissing);
+        assert_eq!(error.context, None);
+    }
+
+    #[test]
+    fn switching_the_register_mode_keeps_counting_steps() {
+        let mut vm = compile("LOAD #3\nADD #1\nEND\n");
+        vm.step().unwrap();
+        vm.set_register_mode(RegisterMode::Integer).unwrap();
+        assert_eq!(vm.snapshot().step, 1);
+        assert_eq!(vm.step_back().unwrap_err(), ExecutionError::HistoryExhausted);
+        vm.step().unwrap();
+        assert_eq!(vm.snapshot().step, 2);
+    }
+
+    /// Compiles `old` from a file, runs `steps` steps and recompiles after changing it to `new`
+    fn recompile(name: &str, old: &str, steps: usize, new: &str) -> (VirtualMachine, Result<PcMapping, CompileError>) {
+        let path = std::env::temp_dir().join(format!("recompile_{}_{}.rm", name, std::process::id()));
+        std::fs::write(&path, old).unwrap();
+        let mut vm: VirtualMachine = VirtualMachine::new();
+        vm.load(&path).unwrap();
+        for _ in 0..steps {
+            vm.step().unwrap();
+        }
+        std::fs::write(&path, new).unwrap();
+        let result = vm.recompile(&path);
+        std::fs::remove_file(&path).unwrap();
+        (vm, result)
+    }
+
+    fn line(vm: &VirtualMachine, line: u32) -> Diagnostics {
+        Diagnostics { line, file: vm.program.as_ref().unwrap().to_string_lossy().into_owned() }
+    }
+
+    #[test]
+    fn recompile_maps_by_label() {
+        let old = "LOAD #1\nloop: ADD #1\nGOTO loop\nEND\n";
+        let (vm, result) = recompile("label", old, 2, "LOAD #1\nloop: ADD #1\nGOTO loop\nSUB #1\nEND\n");
+        assert_eq!(result, Ok(PcMapping::Label { line: line(&vm, 2) }));
+        assert_eq!(vm.snapshot().step, 2);
+    }
+
+    #[test]
+    fn recompile_maps_by_line_without_the_label() {
+        let (vm, result) = recompile("line", "LOAD #1\nloop: ADD #1\nEND\n", 1, "LOAD #1\nADD #1\nEND\n");
+        assert_eq!(result, Ok(PcMapping::Line { line: line(&vm, 1) }));
+    }
+
+    #[test]
+    fn recompile_reports_an_inserted_line_as_ambiguous() {
+        let old = "LOAD #1\nloop: ADD #1\nGOTO loop\nEND\n";
+        let (vm, result) = recompile("ambiguous", old, 2, "LOAD #1\nLOAD #1\nloop: ADD #1\nGOTO loop\nEND\n");
+        assert_eq!(result, Ok(PcMapping::Ambiguous { chosen: line(&vm, 3), alternative: line(&vm, 2) }));
+        assert_eq!(vm.line_ptr, 3);
+    }
+
+    #[test]
+    fn recompile_maps_the_call_stack() {
+        let old = "CALL sub\nEND\nsub: LOAD #1\nRET\n";
+        let (mut vm, result) = recompile("call", old, 1, "CALL sub\nEND\nsub: LOAD #1\nADD #1\nRET\n");
+        assert_eq!(result, Ok(PcMapping::Label { line: line(&vm, 2) }));
+        assert_eq!(vm.call_stack, vec![1]);
+        vm.run(10).unwrap();
+        assert_eq!((vm.line_ptr, vm.accumulator.clone()), (1, BigInt::from(2)));
+    }
+
+    #[test]
+    fn recompile_fails_if_a_return_address_is_lost() {
+        let old = "GOTO start\nsub: LOAD #1\nRET\nstart: CALL sub\nEND\n";
+        let (vm, result) = recompile("lost", old, 2, "GOTO begin\nsub: LOAD #1\nRET\nbegin: CALL sub\n");
+        let file = vm.program.as_ref().unwrap().to_string_lossy().into_owned();
+        assert_eq!(result, Err(CompileError::ReturnAddressLost { file, line: 4 }));
+        assert_eq!(vm.lines.len(), 5);
+        assert_eq!(vm.call_stack, vec![4]);
+    }
+
+    #[test]
+    fn limiting_the_registers_checks_program_and_registers() {
+        let mut vm = compile("LOAD #1\nSTORE 5\nEND\n");
+        vm.run(10).unwrap();
+        assert_eq!(vm.set_max_register(Some(4)), Err(ReconfigureError::RegisterOutOfRange));
+        vm.memory.remove(5);
+        let error = CompileError::InvalidRegister { file: "test.rm".to_owned(), line: 1 };
+        assert_eq!(vm.set_max_register(Some(4)), Err(ReconfigureError::Compile(error)));
+        assert_eq!(vm.max_register, None);
+        vm.set_max_register(Some(5)).unwrap();
+        assert_eq!(vm.max_register, Some(5));
+    }
+
+    #[test]
+    fn step_out_needs_a_subroutine() {
+        let mut vm = compile("CALL sub\nEND\nsub: LOAD #1\nADD #1\nRET\n");
+        assert_eq!(vm.step_out(100).unwrap_err().error, ExecutionError::NotInSubroutine);
+        assert_eq!(vm.snapshot().step, 0);
+        vm.step().unwrap();
+        assert_eq!(vm.step_out(100).unwrap().steps, 3);
+        assert_eq!((vm.line_ptr, vm.call_stack.len()), (1, 0));
+    }
+
+    #[test]
+    fn jumping_beyond_the_end_fails() {
+        let mut vm = compile("LOAD #1\nEND\n");
+        assert_eq!(vm.jump_to_step(5).unwrap_err().error, ExecutionError::EndedBeforeStep { steps: 1 });
+        assert_eq!(vm.jump_to_step(0).unwrap().step, 0);
+        assert_eq!(vm.jump_to_step(1).unwrap().step, 1);
+    }
+}
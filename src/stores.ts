@@ -19,9 +19,12 @@ export const editorApiRef = writable<EditorApi>({
         console.log("showFile not implemented yet");
     }
 });
-export const currentAccumulator = writable<number>(0);
+export const currentAccumulator = writable<string>("0");
 export const currentUserRegisters = writable<number[]>([0]);
-export const currentSystemRegisters = writable<number[]>([]);
+export const currentSystemRegisters = writable<string[]>([]);
+// Whitespace separated values READ takes from the input tape, and the values written so far
+export const currentInputTape = writable<string>("");
+export const currentOutputTape = writable<string[]>([]);
 
 const defaultGlobalLog = (
     msg: string,